@@ -1,23 +1,101 @@
-#![cfg_attr(not(std), no_std)]
-#![feature(adt_const_params)]
-#![feature(generic_const_exprs)]
-#![feature(const_panic)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
 use core::fmt;
 use core::fmt::Formatter;
-use embedded_hal;
 use crc_any::CRCu8;
 
-use core::ops::Sub;
+use core::ops::{Add, Sub, Mul, Div, Neg};
 use bitflags::bitflags;
 
 use serde::{Serialize, Deserialize};
 use core::iter::Sum;
 
+// Register addresses, names as in the datasheet register map
+pub mod regs {
+    pub const SYS_STAT: u8 = 0x00;
+    pub const CELLBAL1: u8 = 0x01;
+    pub const CELLBAL2: u8 = 0x02;
+    pub const CELLBAL3: u8 = 0x03;
+    pub const SYS_CTRL1: u8 = 0x04;
+    pub const SYS_CTRL2: u8 = 0x05;
+    pub const PROTECT1: u8 = 0x06;
+    pub const PROTECT2: u8 = 0x07;
+    pub const PROTECT3: u8 = 0x08;
+    pub const OV_TRIP: u8 = 0x09;
+    pub const UV_TRIP: u8 = 0x0a;
+    pub const CC_CFG: u8 = 0x0b;
+    pub const VC1_HI: u8 = 0x0c;
+    pub const BAT_HI: u8 = 0x2a;
+    pub const BAT_LO: u8 = 0x2b;
+    pub const TS1_HI: u8 = 0x2c;
+    pub const TS2_HI: u8 = 0x2e;
+    pub const TS3_HI: u8 = 0x30;
+    pub const CC_HI: u8 = 0x32;
+    pub const CC_LO: u8 = 0x33;
+    pub const ADCGAIN1: u8 = 0x50;
+    pub const ADCOFFSET: u8 = 0x51;
+    pub const ADCGAIN2: u8 = 0x59;
+}
+
 pub const BQ76920: usize = 5;
 pub const BQ76930: usize = 10;
 pub const BQ76940: usize = 15;
 
+/// Driver types for each chip, `Bq76920` is `BQ769x0<BQ76920>` and so on.
+///
+/// ```
+/// use bq769x0::{Bq76920, Bq76930, Bq76940};
+///
+/// let bq76920 = Bq76920::new(0x08, 5, false).unwrap();
+/// let bq76930 = Bq76930::new(0x08, 10, false).unwrap();
+/// let bq76940 = Bq76940::new(0x08, 15, true).unwrap();
+/// assert_eq!((bq76920.cell_count(), bq76930.cell_count(), bq76940.cell_count()), (5, 10, 15));
+/// ```
+pub type Bq76920 = BQ769x0<BQ76920>;
+pub type Bq76930 = BQ769x0<BQ76930>;
+pub type Bq76940 = BQ769x0<BQ76940>;
+
+// Addresses probed by new_detect(), 0x18 first
+pub const DEFAULT_ADDRESSES: [u8; 2] = [0x18, 0x08];
+
+// VC1_HI..VC15_LO, cell reads use the first X * 2 bytes so no const generic expressions are needed
+const CELL_BLOCK_MAX: usize = BQ76940 * 2;
+const TELEMETRY_LEN: usize = (regs::CC_LO + 1 - regs::VC1_HI) as usize; // VC1_HI..CC_LO
+// Largest read_raw/write_raw transfers, on both CRC and non-CRC paths. Longer buffers give Error::BufTooLarge
+// without touching the bus, empty buffers are a no-op. write_block() splits longer writes.
+pub const MAX_READ_LEN: usize = TELEMETRY_LEN;
+pub const MAX_WRITE_LEN: usize = 8;
+// SYS_STAT..CC_LO, the whole register map except the factory ADC registers
+pub const REGISTER_DUMP_LEN: usize = regs::CC_LO as usize + 1;
+
+// Bus idle time before each transaction used by SettleDelay. The datasheet minimum is the I2C bus free time
+// t_BUF (4.7us in standard mode), the default leaves a wide margin.
+pub const DEFAULT_SETTLE_US: u32 = 50;
+
+// Coulomb counter resolution from the datasheet, 8.44uV across the shunt per LSB
+pub const CC_LSB_NV: u32 = 8440;
+
+// DEVICE_XREADY flags an internal fault, the flag is only cleared after the device had time to recover
+pub const XREADY_RECOVERY_MS: u16 = 1000;
+
+// TS registers only follow a TEMP_SEL change after the next temperature conversion (every 2s)
+pub const TEMP_SOURCE_SWITCH_MS: u16 = 2000;
+
+// SYS_STAT polling interval while waiting for CC_READY, a one-shot conversion takes CC_SAMPLE_PERIOD_MS
+pub const CC_POLL_INTERVAL_MS: u16 = 10;
+
+// Boot timing used by wake() and wake_with_boot_pin(): time for the device to boot after leaving SHIP mode
+// before it answers on I2C, and how long the BOOT (TS1) line is driven high
+pub const WAKE_BOOT_MS: u16 = 10;
+pub const BOOT_PULSE_MS: u16 = 1;
+
+// SHIP sequence timing used by ship_enter_verified(). The datasheet (SLUSBK2, SHIP mode) only requires the
+// SHUT_A/SHUT_B writes 00, 01, 10 in order and gives no timing for them, both values are chosen margins:
+// a gap between the writes so they arrive as separate transactions, and WAKE_BOOT_MS (the time allowed for the
+// device to boot) as the bound for it to power down before the check.
+pub const SHIP_WRITE_DELAY_MS: u16 = 1;
+pub const SHIP_SHUTDOWN_MS: u16 = WAKE_BOOT_MS;
+
 pub struct BQ769x0<const X: usize> {
     dev_address: u8, // 7bit address
     // crc: CRCu8, // x8 + x2 + x + 1
@@ -27,10 +105,22 @@ pub struct BQ769x0<const X: usize> {
     shunt: MicroOhms,
     cell_count: u8,
     cells: [MilliVolts; X],
+    cells_read: bool,
     use_crc: bool,
+    current_polarity: CurrentPolarity,
+    current_offset: MilliAmperes,
+    cell_wiring: CellWiring,
+    crc_retries: u8,
+    last_faults: SysStat,
+    cc_lsb_nv: u32, // nV across the shunt per CC LSB
+    balance_timer: Option<(u32, u32)>, // (max on-time, elapsed) in ms, see balance_with_timeout
+    temp_source: Option<TemperatureSource>, // TEMP_SEL as last written, None until known
+    dsg_floor: Option<MilliVolts>, // see set_discharge_floor
+    cell_offsets: [i16; X], // mV, per logical cell, see set_cell_offsets
 }
 
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
     // #[cfg(crc)]
     CRCMismatch,
@@ -38,9 +128,43 @@ pub enum Error {
     BufTooLarge,
     Uninitialized,
     VerifyError(u8),
-    OCDSCDRangeMismatch,
+    OCDSCDRangeMismatch(OCDSCDRangeConflict),
     UVThresholdUnobtainable(MilliVolts, MilliVolts),
     OVThresholdUnobtainable(MilliVolts, MilliVolts),
+    AdjacentBalancing,
+    Timeout,
+    PinError,
+    VariantMismatch(usize),
+    InvalidCellWiring,
+    ZeroShunt,
+    XReadyPersistent,
+    InvalidCell(usize),
+    // Device only answers with the other CRC mode, carries the working use_crc value
+    CRCModeMismatch(bool),
+    InvalidCellCount(u8),
+    InvalidVariant(usize),
+    // Requested current is above the top step with this shunt, carries the highest obtainable one
+    SCDThresholdUnobtainable(Amperes),
+    OCDThresholdUnobtainable(Amperes),
+    ActiveFaults(SysStat),
+    // Discharge refused, physical cell index and voltage of the first cell below the discharge floor
+    CellBelowFloor(usize, MilliVolts),
+    // Device still answers after the SHIP mode sequence
+    ShipModeFailed,
+    // Caller buffer shorter than needed, carries the required length
+    BufTooSmall(usize),
+    // calibrate_current_offset() called with no samples to average
+    ZeroSamples,
+}
+
+// Either move SCD to the suggested value (fits OCD range) or OCD to its suggested value (fits SCD range)
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OCDSCDRangeConflict {
+    pub scd_range: OCDSCDRange,
+    pub ocd_range: OCDSCDRange,
+    pub suggested_scd_threshold: Amperes,
+    pub suggested_ocd_threshold: Amperes,
 }
 
 // impl<E> From<E> for Error
@@ -51,10 +175,12 @@ pub enum Error {
 //     }
 // }
 
+#[deprecated(note = "sys_stat() returns SysStat now, use it for both reading and clearing flags")]
 pub struct Stat {
     bits: u8
 }
 
+#[allow(deprecated)]
 impl Stat {
     pub fn cc_ready_is_set(&self) -> bool { self.bits & (1u8 << 7) != 0 }
     pub fn device_xready_is_set(&self) -> bool { self.bits & (1u8 << 5) != 0 }
@@ -67,6 +193,8 @@ impl Stat {
     pub fn is_ok(&self) -> bool { self.bits & 0b0011_1111 == 0 }
 }
 
+// Every SYS_STAT flag is latched: it stays set until the host writes 1 to it, none tracks the live condition
+// (bit 6 is reserved). UV, OV and OVRD_ALERT are set again shortly after clearing while the condition persists.
 bitflags! {
     pub struct SysStat: u8 {
         const CC_READY      = 0b1000_0000;
@@ -80,6 +208,74 @@ bitflags! {
     }
 }
 
+bitflags! {
+    pub struct SysCtrl1: u8 {
+        const LOAD_PRESENT  = 0b1000_0000;
+        const ADC_EN        = 0b0001_0000;
+        const TEMP_SEL      = 0b0000_1000;
+        const SHUT_A        = 0b0000_0010;
+        const SHUT_B        = 0b0000_0001;
+    }
+}
+
+bitflags! {
+    pub struct SysCtrl2: u8 {
+        const DELAY_DIS     = 0b1000_0000;
+        const CC_EN         = 0b0100_0000;
+        const CC_ONESHOT    = 0b0010_0000;
+        const DSG_ON        = 0b0000_0010;
+        const CHG_ON        = 0b0000_0001;
+    }
+}
+
+impl SysStat {
+    // No fault flags set, CC_READY is not a fault
+    pub fn is_ok(&self) -> bool { self.bits() & 0b0011_1111 == 0 }
+}
+
+// Serialized as the raw SYS_STAT byte, the reserved bit is dropped on deserialization
+impl Serialize for SysStat {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(self.bits())
+    }
+}
+
+impl<'de> Deserialize<'de> for SysStat {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u8::deserialize(deserializer).map(SysStat::from_bits_truncate)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for SysStat {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "(");
+        if self.contains(SysStat::CC_READY) {
+            defmt::write!(f, "CC_READY, ");
+        }
+        if self.contains(SysStat::DEVICE_XREADY) {
+            defmt::write!(f, "XREADY, ");
+        }
+        if self.contains(SysStat::OVRD_ALERT) {
+            defmt::write!(f, "ALERT, ");
+        }
+        if self.contains(SysStat::UNDERVOLTAGE) {
+            defmt::write!(f, "UV, ");
+        }
+        if self.contains(SysStat::OVERVOLTAGE) {
+            defmt::write!(f, "OV, ");
+        }
+        if self.contains(SysStat::SHORTCIRCUIT) {
+            defmt::write!(f, "SCD, ");
+        }
+        if self.contains(SysStat::OVERCURRENT) {
+            defmt::write!(f, "OCD, ");
+        }
+        defmt::write!(f, ")");
+    }
+}
+
+#[allow(deprecated)]
 impl fmt::Debug for Stat {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         let _ = write!(f, "(");
@@ -108,6 +304,38 @@ impl fmt::Debug for Stat {
     }
 }
 
+#[cfg(feature = "defmt")]
+#[allow(deprecated)]
+impl defmt::Format for Stat {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "(");
+        if self.cc_ready_is_set() {
+            defmt::write!(f, "CC_READY, ");
+        }
+        if self.device_xready_is_set() {
+            defmt::write!(f, "XREADY, ");
+        }
+        if self.ovrd_alert_is_set() {
+            defmt::write!(f, "ALERT, ");
+        }
+        if self.undervoltage_is_set() {
+            defmt::write!(f, "UV, ");
+        }
+        if self.overvoltage_is_set() {
+            defmt::write!(f, "OV, ");
+        }
+        if self.scd_is_set() {
+            defmt::write!(f, "SCD, ");
+        }
+        if self.ocd_is_set() {
+            defmt::write!(f, "OCD, ");
+        }
+        defmt::write!(f, ")");
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum SCDDelay {
     _70uS,
     _100uS,
@@ -124,24 +352,232 @@ impl SCDDelay {
             SCDDelay::_400uS => { 0x3 << 3 },
         }
     }
+
+    // Nominal delay, no millis() as all steps are below 1ms
+    pub fn micros(&self) -> u32 {
+        match self {
+            SCDDelay::_70uS => 70,
+            SCDDelay::_100uS => 100,
+            SCDDelay::_200uS => 200,
+            SCDDelay::_400uS => 400,
+        }
+    }
+}
+
+// Add, checked add/sub and scaling by a plain number, shared by all unit newtypes
+macro_rules! impl_unit_ops {
+    ($unit:ident, $scalar:ty) => {
+        impl $unit {
+            pub fn checked_add(self, rhs: Self) -> Option<$unit> {
+                self.0.checked_add(rhs.0).map($unit)
+            }
+
+            pub fn checked_sub(self, rhs: Self) -> Option<$unit> {
+                self.0.checked_sub(rhs.0).map($unit)
+            }
+
+            pub fn saturating_sub(self, rhs: Self) -> $unit {
+                $unit(self.0.saturating_sub(rhs.0))
+            }
+        }
+
+        impl Add for $unit {
+            type Output = $unit;
+
+            fn add(self, rhs: Self) -> Self::Output {
+                $unit(self.0 + rhs.0)
+            }
+        }
+
+        impl Mul<$scalar> for $unit {
+            type Output = $unit;
+
+            fn mul(self, rhs: $scalar) -> Self::Output {
+                $unit(self.0 * rhs)
+            }
+        }
+
+        impl Div<$scalar> for $unit {
+            type Output = $unit;
+
+            fn div(self, rhs: $scalar) -> Self::Output {
+                $unit(self.0 / rhs)
+            }
+        }
+
+        impl Sum for $unit {
+            fn sum<I: Iterator<Item=Self>>(iter: I) -> Self {
+                $unit(iter.map(|u| u.0).sum())
+            }
+        }
+    };
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Amperes(pub u32);
+impl_unit_ops!(Amperes, u32);
+
+// Saturates at 0A like MilliVolts, use checked_sub to detect underflow
+impl Sub for Amperes {
+    type Output = Amperes;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.saturating_sub(rhs)
+    }
+}
 
 #[derive(Debug, PartialEq, PartialOrd, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct MilliAmperes(pub i32);
+impl_unit_ops!(MilliAmperes, i32);
+
+impl Sub for MilliAmperes {
+    type Output = MilliAmperes;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        MilliAmperes(self.0 - rhs.0)
+    }
+}
 
-#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+impl Neg for MilliAmperes {
+    type Output = MilliAmperes;
+
+    fn neg(self) -> Self::Output {
+        MilliAmperes(-self.0)
+    }
+}
+
+impl MilliAmperes {
+    // Rounds to the nearest whole ampere, halves away from zero
+    pub fn to_amperes(self) -> SignedAmperes {
+        let half = if self.0 < 0 { -500 } else { 500 };
+        SignedAmperes((self.0 + half) / 1000)
+    }
+}
+
+// Signed whole amperes for pack current, Amperes is unsigned as it is used for protection thresholds
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SignedAmperes(pub i32);
+impl_unit_ops!(SignedAmperes, i32);
+
+impl Sub for SignedAmperes {
+    type Output = SignedAmperes;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        SignedAmperes(self.0 - rhs.0)
+    }
+}
+
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct MicroOhms(pub u32);
+impl_unit_ops!(MicroOhms, u32);
+
+impl Sub for MicroOhms {
+    type Output = MicroOhms;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.saturating_sub(rhs)
+    }
+}
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct MilliVolts(pub u32);
+impl_unit_ops!(MilliVolts, u32);
+
+// Saturates at 0mV, noisy readings must not panic when computing deltas
 impl Sub for MilliVolts {
     type Output = MilliVolts;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        MilliVolts(self.0 - rhs.0)
+        self.saturating_sub(rhs)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MilliWatts(pub i32);
+impl MilliWatts {
+    pub fn from_voltage_current(voltage: MilliVolts, current: MilliAmperes) -> Self {
+        MilliWatts((voltage.0 as i64 * current.0 as i64 / 1000) as i32)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MilliWattHours(pub i32);
+
+// Integrates power samples over time, keeps the sub-mWh remainder between samples
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct EnergyAccumulator {
+    mw_ms: i64,
+}
+
+impl EnergyAccumulator {
+    pub fn new() -> Self {
+        EnergyAccumulator { mw_ms: 0 }
+    }
+
+    pub fn add(&mut self, power: MilliWatts, elapsed_ms: u32) {
+        self.mw_ms += power.0 as i64 * elapsed_ms as i64;
+    }
+
+    pub fn energy(&self) -> MilliWattHours {
+        MilliWattHours((self.mw_ms / 3_600_000) as i32)
+    }
+
+    pub fn reset(&mut self) {
+        self.mw_ms = 0;
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MilliAmpereHours(pub i32);
+
+// Coulomb counter conversion period in continuous mode (CC_READY is set after each one)
+pub const CC_SAMPLE_PERIOD_MS: u32 = 250;
+
+// Sums continuous mode CC samples, each one stands for CC_SAMPLE_PERIOD_MS of current.
+// The CC register only holds the last sample, so it has to be read at least every 250ms to not miss any,
+// see BQ769x0::accumulate_charge().
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct ChargeAccumulator {
+    ma_samples: i64,
+    samples: u32,
+}
+
+impl ChargeAccumulator {
+    pub fn new() -> Self {
+        ChargeAccumulator { ma_samples: 0, samples: 0 }
+    }
+
+    pub fn add(&mut self, current: MilliAmperes) {
+        self.ma_samples += current.0 as i64;
+        self.samples = self.samples.saturating_add(1);
+    }
+
+    // Net charge since reset, sign follows current()
+    pub fn charge(&self) -> MilliAmpereHours {
+        MilliAmpereHours((self.ma_samples * CC_SAMPLE_PERIOD_MS as i64 / 3_600_000) as i32)
+    }
+
+    pub fn average_current(&self) -> MilliAmperes {
+        if self.samples == 0 {
+            return MilliAmperes(0);
+        }
+        MilliAmperes((self.ma_samples / self.samples as i64) as i32)
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
     }
 }
 
@@ -157,13 +593,32 @@ impl fmt::Display for MilliAmperes {
     }
 }
 
+impl fmt::Display for MilliWatts {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}mW", self.0)
+    }
+}
+
+impl fmt::Display for MilliAmpereHours {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}mAh", self.0)
+    }
+}
+
+impl fmt::Display for MilliWattHours {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}mWh", self.0)
+    }
+}
+
 impl fmt::Display for MilliVolts {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{}mV", self.0)
     }
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct DegreesCentigrade(pub i32);
 impl fmt::Display for DegreesCentigrade {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
@@ -171,6 +626,31 @@ impl fmt::Display for DegreesCentigrade {
     }
 }
 
+// Tenths of a degree, e.g. DeciCelsius(450) = 45.0degC
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeciCelsius(pub i32);
+impl DeciCelsius {
+    // Rounds to the nearest whole degree, halves away from zero
+    pub fn to_degrees(self) -> DegreesCentigrade {
+        let half = if self.0 < 0 { -5 } else { 5 };
+        DegreesCentigrade((self.0 + half) / 10)
+    }
+}
+
+impl From<DegreesCentigrade> for DeciCelsius {
+    fn from(t: DegreesCentigrade) -> Self {
+        DeciCelsius(t.0 * 10)
+    }
+}
+
+impl fmt::Display for DeciCelsius {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        write!(f, "{}{}.{}degC", sign, (self.0 / 10).abs(), (self.0 % 10).abs())
+    }
+}
+
 #[derive(Copy, Clone)]
 pub enum SCDThreshold {
     // Lower range (RSNS = 0)
@@ -193,7 +673,8 @@ pub enum SCDThreshold {
     _200mV  = 200
 }
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum OCDSCDRange {
     Lower,
     Upper,
@@ -260,12 +741,48 @@ impl SCDThreshold {
         unreachable!();
     }
 
+    // Thresholds above the top step are pinned to it, see from_current_checked
     pub fn from_current(threshold: Amperes, shunt: MicroOhms) -> Self {
-        let mv_threshold = threshold.0 * shunt.0 / 1000;
-        Self::from_mv(mv_threshold as u8)
+        let mv_threshold = threshold.0 as u64 * shunt.0 as u64 / 1000;
+        Self::from_mv(mv_threshold.min(u8::MAX as u64) as u8)
+    }
+
+    // Same step as from_current, plus the current it actually trips at with this shunt
+    pub fn from_current_actual(threshold: Amperes, shunt: MicroOhms) -> (Self, Amperes) {
+        let step = Self::from_current(threshold, shunt);
+        (step, Amperes(((step as u32) * 1000).checked_div(shunt.0).unwrap_or(u32::MAX)))
+    }
+
+    // Error::SCDThresholdUnobtainable(highest current) instead of pinning to the top step
+    pub fn from_current_checked(threshold: Amperes, shunt: MicroOhms) -> Result<Self, Error> {
+        let (step, actual) = Self::from_current_actual(threshold, shunt);
+        if matches!(step, SCDThreshold::_200mV) && actual < threshold {
+            return Err(Error::SCDThresholdUnobtainable(actual));
+        }
+        Ok(step)
+    }
+
+    // Steps selectable with this RSNS range, indexed by the PROTECT1 SCD_T bits
+    fn steps(range: OCDSCDRange) -> [Self; 8] {
+        use SCDThreshold::*;
+        match range {
+            OCDSCDRange::Upper => [_44mV, _67mV, _89mV, _111mV, _133mV, _155mV, _178mV, _200mV],
+            _ => [_22mV, _33mV, _44mV, _56mV, _67mV, _78mV, _89mV, _100mV],
+        }
+    }
+
+    pub fn nearest_in_range(mv_threshold: u8, range: OCDSCDRange) -> Self {
+        *Self::steps(range).iter().min_by_key(|t| (**t as i16 - mv_threshold as i16).abs()).unwrap()
+    }
+
+    // Inverse of bits(), SCD_T field of PROTECT1 (upper bits are ignored)
+    pub fn from_bits(bits: u8, range: OCDSCDRange) -> Self {
+        Self::steps(range)[(bits & 0x7) as usize]
     }
 }
 
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum OCDDelay {
     _8ms    = 0x0,
     _20ms   = 0x1,
@@ -290,6 +807,24 @@ impl OCDDelay {
             OCDDelay::_1280ms => { 0x7 << 4 },
         }
     }
+
+    // Nominal delay
+    pub fn millis(&self) -> u32 {
+        match self {
+            OCDDelay::_8ms => 8,
+            OCDDelay::_20ms => 20,
+            OCDDelay::_40ms => 40,
+            OCDDelay::_80ms => 80,
+            OCDDelay::_160ms => 160,
+            OCDDelay::_320ms => 320,
+            OCDDelay::_640ms => 640,
+            OCDDelay::_1280ms => 1280,
+        }
+    }
+
+    pub fn micros(&self) -> u32 {
+        self.millis() * 1000
+    }
 }
 
 #[derive(Copy, Clone, PartialEq)]
@@ -385,12 +920,50 @@ impl OCDThreshold {
         unreachable!();
     }
 
+    // Thresholds above the top step are pinned to it, see from_current_checked
     pub fn from_current(threshold: Amperes, shunt: MicroOhms) -> Self {
-        let mv_threshold = threshold.0 * shunt.0 / 1000;
-        Self::from_mv(mv_threshold as u8)
+        let mv_threshold = threshold.0 as u64 * shunt.0 as u64 / 1000;
+        Self::from_mv(mv_threshold.min(u8::MAX as u64) as u8)
+    }
+
+    // Same step as from_current, plus the current it actually trips at with this shunt
+    pub fn from_current_actual(threshold: Amperes, shunt: MicroOhms) -> (Self, Amperes) {
+        let step = Self::from_current(threshold, shunt);
+        (step, Amperes(((step as u32) * 1000).checked_div(shunt.0).unwrap_or(u32::MAX)))
+    }
+
+    // Error::OCDThresholdUnobtainable(highest current) instead of pinning to the top step
+    pub fn from_current_checked(threshold: Amperes, shunt: MicroOhms) -> Result<Self, Error> {
+        let (step, actual) = Self::from_current_actual(threshold, shunt);
+        if matches!(step, OCDThreshold::_100mV) && actual < threshold {
+            return Err(Error::OCDThresholdUnobtainable(actual));
+        }
+        Ok(step)
+    }
+
+    // Steps selectable with this RSNS range, indexed by the PROTECT2 OCD_T bits
+    fn steps(range: OCDSCDRange) -> [Self; 16] {
+        use OCDThreshold::*;
+        match range {
+            OCDSCDRange::Upper => [_17mV, _22mV, _28mV, _33mV, _39mV, _44mV, _50mV, _56mV,
+                _61mV, _67mV, _72mV, _78mV, _83mV, _89mV, _94mV, _100mV],
+            _ => [_8mV, _11mV, _14mV, _17mV, _19mV, _22mV, _25mV, _28mV,
+                _31mV, _33mV, _36mV, _39mV, _42mV, _44mV, _47mV, _50mV],
+        }
+    }
+
+    pub fn nearest_in_range(mv_threshold: u8, range: OCDSCDRange) -> Self {
+        *Self::steps(range).iter().min_by_key(|t| (**t as i16 - mv_threshold as i16).abs()).unwrap()
+    }
+
+    // Inverse of bits(), OCD_T field of PROTECT2 (upper bits are ignored)
+    pub fn from_bits(bits: u8, range: OCDSCDRange) -> Self {
+        Self::steps(range)[(bits & 0xf) as usize]
     }
 }
 
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum UVDelay {
     _1s  = 0x0,
     _4s  = 0x1,
@@ -407,8 +980,24 @@ impl UVDelay {
             UVDelay::_16s => { 0x3 << 6 },
         }
     }
+
+    // Nominal delay
+    pub fn millis(&self) -> u32 {
+        match self {
+            UVDelay::_1s => 1000,
+            UVDelay::_4s => 4000,
+            UVDelay::_8s => 8000,
+            UVDelay::_16s => 16000,
+        }
+    }
+
+    pub fn micros(&self) -> u32 {
+        self.millis() * 1000
+    }
 }
 
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum OVDelay {
     _1s  = 0x0,
     _4s  = 0x1,
@@ -425,6 +1014,20 @@ impl OVDelay {
             OVDelay::_16s => { 0x3 << 4 },
         }
     }
+
+    // Nominal delay
+    pub fn millis(&self) -> u32 {
+        match self {
+            OVDelay::_1s => 1000,
+            OVDelay::_4s => 4000,
+            OVDelay::_8s => 8000,
+            OVDelay::_16s => 16000,
+        }
+    }
+
+    pub fn micros(&self) -> u32 {
+        self.millis() * 1000
+    }
 }
 
 pub struct Config {
@@ -437,88 +1040,338 @@ pub struct Config {
     pub uv_threshold: MilliVolts,
     pub ov_delay: OVDelay,
     pub ov_threshold: MilliVolts,
+    pub cc_cfg: u8,
+    // Recovery distance from the trip points, only used for CalculatedValues::ov_recovery/uv_recovery
+    pub ov_hysteresis: MilliVolts,
+    pub uv_hysteresis: MilliVolts,
 }
 
-#[derive(Debug)]
-pub struct CalculatedValues {
-    pub ocdscd_range_used: OCDSCDRange,
-    pub scd_threshold: Amperes,
-    pub ocd_threshold: Amperes,
-    pub uv_threshold: MilliVolts,
-    pub ov_threshold: MilliVolts
-}
+// Datasheet value for CC_CFG (0x0B), other values are for experimentation only
+pub const CC_CFG_RECOMMENDED: u8 = 0x19;
 
-impl<const X: usize> BQ769x0<X> where [(); X * 2]: Sized, [(); X * 4]: Sized {
-    pub const fn new(dev_address: u8, cell_count: u8, use_crc: bool) -> Option<Self> {
-        match X {
-            BQ76920 | BQ76930 | BQ76940 => {
-                match X {
-                    BQ76920 => {
-                        if cell_count < 3 || cell_count > 5 {
-                            return None;
-                        }
-                    }
-                    BQ76930 => {
-                        if cell_count < 6 || cell_count > 10 {
-                            return None;
-                        }
-                    }
-                    BQ76940 => {
-                        if cell_count < 9 || cell_count > 15 {
-                            return None;
-                        }
-                    },
-                    _ => unreachable!()
-                }
-                Some(BQ769x0 {
-                    dev_address,
-                    init_complete: false,
-                    adc_gain: 0,
-                    adc_offset: 0,
-                    shunt: MicroOhms(0),
-                    cell_count,
-                    cells: [MilliVolts(0); X],
-                    use_crc
-                })
-            },
-            _ => {
-                None
-            }
-        }
-    }
+// Default recovery hysteresis around the OV/UV trip points. The device does not auto-clear the faults, so
+// firmware should only clear them and re-enable CHG/DSG once cells are this far back from the trip point.
+// The datasheet specifies no hysteresis, 100mV is a conservative choice for Li-ion cells that relax by some
+// tens of mV after charge or load is removed, set it per chemistry in Config.
+pub const DEFAULT_OV_HYSTERESIS: MilliVolts = MilliVolts(100);
+pub const DEFAULT_UV_HYSTERESIS: MilliVolts = MilliVolts(100);
 
-    fn check_communication<I2C>(i2c: &mut I2C, dev_address: u8, use_crc: bool) -> Result<(), Error>
-        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
-    {
-        const TEST_REG: u8 = 0x0a;
-        let mut buf = [0u8; 1];
-        if use_crc {
-            Self::write_raw_crc(i2c, dev_address, TEST_REG, &[0xaa])?;
-            Self::read_raw_crc(i2c, dev_address, TEST_REG, &mut buf)?;
-        } else {
-            Self::write_raw_nocrc(i2c, dev_address, TEST_REG, &[0xaa])?;
-            Self::read_raw_nocrc(i2c, dev_address, TEST_REG, &mut buf)?;
-        }
-        if buf[0] == 0xaa {
-            Ok(())
-        } else {
-            Err(Error::I2CError)
-        }
+impl Config {
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::new()
     }
 
-    pub fn new_detect<I2C>(i2c: &mut I2C, cell_count: u8) -> Option<Self>
-        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
-    {
-        if Self::check_communication(i2c, 0x18, false).is_ok() {
-            return Self::new(0x18, cell_count, false);
-        } else if Self::check_communication(i2c, 0x18, true).is_ok() {
-            return Self::new(0x18, cell_count, true);
-        } else if Self::check_communication(i2c, 0x08, false).is_ok() {
-            return Self::new(0x08, cell_count, false);
-        } else if Self::check_communication(i2c, 0x08, true).is_ok() {
-            return Self::new(0x08, cell_count, true);
-        } else {
-            None
+    // Resolves the RSNS range shared by SCD and OCD thresholds without touching hardware
+    pub fn check_ocdscd_range(&self) -> Result<OCDSCDRange, Error> {
+        if self.shunt.0 == 0 {
+            return Err(Error::ZeroShunt);
+        }
+        let scd_threshold = SCDThreshold::from_current_checked(self.scd_threshold, self.shunt)?;
+        let ocd_threshold = OCDThreshold::from_current_checked(self.ocd_threshold, self.shunt)?;
+        OCDSCDRange::resolve(scd_threshold, ocd_threshold, self.shunt)
+    }
+}
+
+impl OCDSCDRange {
+    // RSNS setting that fits both steps, OCDSCDRangeMismatch with suggestions if there is none
+    fn resolve(scd_threshold: SCDThreshold, ocd_threshold: OCDThreshold, shunt: MicroOhms) -> Result<OCDSCDRange, Error> {
+        let scd_range = scd_threshold.range();
+        let ocd_range = ocd_threshold.range();
+        if (scd_range == OCDSCDRange::Lower && ocd_range == OCDSCDRange::Upper) ||
+            (scd_range == OCDSCDRange::Upper && ocd_range == OCDSCDRange::Lower) {
+            let suggested_scd = SCDThreshold::nearest_in_range(scd_threshold as u8, ocd_range);
+            let suggested_ocd = OCDThreshold::nearest_in_range(ocd_threshold as u8, scd_range);
+            return Err(Error::OCDSCDRangeMismatch(OCDSCDRangeConflict {
+                scd_range,
+                ocd_range,
+                suggested_scd_threshold: Amperes(((suggested_scd as u32) * 1000) / shunt.0),
+                suggested_ocd_threshold: Amperes(((suggested_ocd as u32) * 1000) / shunt.0),
+            }));
+        }
+        let range_to_use = if scd_range == OCDSCDRange::Unknown {
+            if ocd_range == OCDSCDRange::Unknown {
+                OCDSCDRange::Lower
+            } else {
+                ocd_range
+            }
+        } else if ocd_range == OCDSCDRange::Unknown {
+            if scd_range == OCDSCDRange::Unknown {
+                OCDSCDRange::Lower
+            } else {
+                scd_range
+            }
+        } else {
+            ocd_range // both ranges are equal
+        };
+        Ok(range_to_use)
+    }
+}
+
+pub struct ConfigBuilder {
+    config: Config
+}
+
+impl ConfigBuilder {
+    // Defaults: 1mOhm shunt, 50A/200us SCD, 20A/320ms OCD, Li-ion 2.8V/4.2V UV/OV with 1s delays,
+    // DEFAULT_OV_HYSTERESIS/DEFAULT_UV_HYSTERESIS
+    pub fn new() -> Self {
+        ConfigBuilder {
+            config: Config {
+                shunt: MicroOhms(1000),
+                scd_delay: SCDDelay::_200uS,
+                scd_threshold: Amperes(50),
+                ocd_delay: OCDDelay::_320ms,
+                ocd_threshold: Amperes(20),
+                uv_delay: UVDelay::_1s,
+                uv_threshold: MilliVolts(2800),
+                ov_delay: OVDelay::_1s,
+                ov_threshold: MilliVolts(4200),
+                cc_cfg: CC_CFG_RECOMMENDED,
+                ov_hysteresis: DEFAULT_OV_HYSTERESIS,
+                uv_hysteresis: DEFAULT_UV_HYSTERESIS,
+            }
+        }
+    }
+
+    pub fn shunt(mut self, shunt: MicroOhms) -> Self {
+        self.config.shunt = shunt;
+        self
+    }
+
+    pub fn scd(mut self, threshold: Amperes, delay: SCDDelay) -> Self {
+        self.config.scd_threshold = threshold;
+        self.config.scd_delay = delay;
+        self
+    }
+
+    pub fn ocd(mut self, threshold: Amperes, delay: OCDDelay) -> Self {
+        self.config.ocd_threshold = threshold;
+        self.config.ocd_delay = delay;
+        self
+    }
+
+    pub fn uv_threshold(mut self, threshold: MilliVolts) -> Self {
+        self.config.uv_threshold = threshold;
+        self
+    }
+
+    pub fn uv_delay(mut self, delay: UVDelay) -> Self {
+        self.config.uv_delay = delay;
+        self
+    }
+
+    pub fn ov_threshold(mut self, threshold: MilliVolts) -> Self {
+        self.config.ov_threshold = threshold;
+        self
+    }
+
+    pub fn ov_delay(mut self, delay: OVDelay) -> Self {
+        self.config.ov_delay = delay;
+        self
+    }
+
+    pub fn cc_cfg(mut self, cc_cfg: u8) -> Self {
+        self.config.cc_cfg = cc_cfg;
+        self
+    }
+
+    pub fn hysteresis(mut self, ov: MilliVolts, uv: MilliVolts) -> Self {
+        self.config.ov_hysteresis = ov;
+        self.config.uv_hysteresis = uv;
+        self
+    }
+
+    pub fn build(self) -> Config {
+        self.config
+    }
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct CalculatedValues {
+    pub ocdscd_range_used: OCDSCDRange,
+    pub scd_threshold: Amperes,
+    pub ocd_threshold: Amperes,
+    pub uv_threshold: MilliVolts,
+    pub ov_threshold: MilliVolts,
+    pub uv_recovery: MilliVolts, // uv_threshold + Config::uv_hysteresis
+    pub ov_recovery: MilliVolts, // ov_threshold - Config::ov_hysteresis
+    // Passed through from Config so to_config() can rebuild it
+    pub shunt: MicroOhms,
+    pub scd_delay: SCDDelay,
+    pub ocd_delay: OCDDelay,
+    pub uv_delay: UVDelay,
+    pub ov_delay: OVDelay,
+    pub cc_cfg: u8,
+}
+
+impl CalculatedValues {
+    pub fn is_ov_recovered(&self, cell: MilliVolts) -> bool {
+        cell <= self.ov_recovery
+    }
+
+    pub fn is_uv_recovered(&self, cell: MilliVolts) -> bool {
+        cell >= self.uv_recovery
+    }
+
+    // Cell is clear of both trip points including hysteresis
+    pub fn is_recovered(&self, cell: MilliVolts) -> bool {
+        self.is_ov_recovered(cell) && self.is_uv_recovered(cell)
+    }
+
+    // Config with the thresholds actually programmed, applying it again gives the same registers and values
+    pub fn to_config(&self) -> Config {
+        Config {
+            shunt: self.shunt,
+            scd_delay: self.scd_delay,
+            scd_threshold: self.scd_threshold,
+            ocd_delay: self.ocd_delay,
+            ocd_threshold: self.ocd_threshold,
+            uv_delay: self.uv_delay,
+            uv_threshold: self.uv_threshold,
+            ov_delay: self.ov_delay,
+            ov_threshold: self.ov_threshold,
+            cc_cfg: self.cc_cfg,
+            ov_hysteresis: self.ov_threshold - self.ov_recovery,
+            uv_hysteresis: self.uv_recovery - self.uv_threshold,
+        }
+    }
+}
+
+impl<const X: usize> BQ769x0<X> {
+    // Number of cell inputs of the chip, for sizing buffers in code generic over X
+    pub const MAX_CELLS: usize = X;
+    // Number of TS inputs, one per 5-cell group, and the length of temperature_all()
+    pub const TS_INPUTS: usize = X / 5;
+
+    pub const fn new(dev_address: u8, cell_count: u8, use_crc: bool) -> Option<Self> {
+        match Self::try_new(dev_address, cell_count, use_crc) {
+            Ok(bq769x0) => Some(bq769x0),
+            Err(_) => None
+        }
+    }
+
+    // InvalidVariant(X) if X is not one of BQ76920/30/40, InvalidCellCount if cell_count is out of the chip's range
+    pub const fn try_new(dev_address: u8, cell_count: u8, use_crc: bool) -> Result<Self, Error> {
+        let (min_cells, max_cells) = match X {
+            BQ76920 => (3, 5),
+            BQ76930 => (6, 10),
+            BQ76940 => (9, 15),
+            _ => return Err(Error::InvalidVariant(X))
+        };
+        if cell_count < min_cells || cell_count > max_cells {
+            return Err(Error::InvalidCellCount(cell_count));
+        }
+        Ok(BQ769x0 {
+            dev_address,
+            init_complete: false,
+            adc_gain: 0,
+            adc_offset: 0,
+            shunt: MicroOhms(0),
+            cell_count,
+            cells: [MilliVolts(0); X],
+            cells_read: false,
+            use_crc,
+            current_polarity: CurrentPolarity::ChargePositive,
+            current_offset: MilliAmperes(0),
+            cell_wiring: CellWiring::Spread,
+            crc_retries: 0,
+            last_faults: SysStat::empty(),
+            cc_lsb_nv: CC_LSB_NV,
+            balance_timer: None,
+            temp_source: None,
+            dsg_floor: None,
+            cell_offsets: [0; X]
+        })
+    }
+
+    fn check_communication<I2C>(i2c: &mut I2C, dev_address: u8, use_crc: bool) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        const TEST_REG: u8 = regs::UV_TRIP;
+        let mut buf = [0u8; 1];
+        if use_crc {
+            Self::write_raw_crc(i2c, dev_address, TEST_REG, &[0xaa])?;
+            Self::read_raw_crc(i2c, dev_address, TEST_REG, &mut buf)?;
+        } else {
+            Self::write_raw_nocrc(i2c, dev_address, TEST_REG, &[0xaa])?;
+            Self::read_raw_nocrc(i2c, dev_address, TEST_REG, &mut buf)?;
+        }
+        if buf[0] == 0xaa {
+            Ok(())
+        } else {
+            Err(Error::I2CError)
+        }
+    }
+
+    // Like new(), but talks to the device first. CRCModeMismatch if it only answers in the other CRC mode,
+    // I2CError if it does not answer at all.
+    pub fn new_checked<I2C>(i2c: &mut I2C, dev_address: u8, cell_count: u8, use_crc: bool) -> Result<Self, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        let bq769x0 = Self::try_new(dev_address, cell_count, use_crc)?;
+        if Self::check_communication(i2c, dev_address, use_crc).is_ok() {
+            Ok(bq769x0)
+        } else if Self::check_communication(i2c, dev_address, !use_crc).is_ok() {
+            Err(Error::CRCModeMismatch(!use_crc))
+        } else {
+            Err(Error::I2CError)
+        }
+    }
+
+    pub fn new_detect<I2C>(i2c: &mut I2C, cell_count: u8) -> Option<Self>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        Self::new_detect_at(i2c, cell_count, &DEFAULT_ADDRESSES)
+    }
+
+    // Probes the addresses in order, each without and then with CRC. i2c_address() and is_crc_used() of the
+    // returned instance tell which combination answered.
+    pub fn new_detect_at<I2C>(i2c: &mut I2C, cell_count: u8, addresses: &[u8]) -> Option<Self>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        for &address in addresses {
+            if let Some(found) = probe(i2c, address) {
+                return Self::new(address, cell_count, found.use_crc);
+            }
+        }
+        None
+    }
+
+    // No ID register on the chip: CELLBAL2 (0x02) and CELLBAL3 (0x03) only exist on BQ76930/40 and read 0
+    // on smaller parts. There is no read-only way to tell an idle CELLBAL register from a missing one, so
+    // a register reading 0 gets cell 0 of its group written and read back, then the value read before is
+    // written again, also when the read-back fails.
+    // Side effect: on a BQ76930/40 balancing of cell 5 (and cell 10 on a BQ76940) is on for those few bus
+    // transactions. Registers with balancing already active are only read.
+    // Returns the detected variant or VariantMismatch(detected) if it differs from X.
+    pub fn detect_variant<I2C>(&mut self, i2c: &mut I2C) -> Result<usize, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        let mut detected = BQ76920;
+        for (reg, variant) in [(regs::CELLBAL2, BQ76930), (regs::CELLBAL3, BQ76940)].iter() {
+            let previous = self.read_u8(i2c, *reg)?;
+            let mut present = previous != 0;
+            if !present {
+                self.write_raw(i2c, *reg, &[0b0_0001])?;
+                let read_back = self.read_u8(i2c, *reg);
+                self.write_raw(i2c, *reg, &[previous])?;
+                present = read_back? != 0;
+            }
+            if !present {
+                break;
+            }
+            detected = *variant;
+        }
+        if detected == X {
+            Ok(detected)
+        } else {
+            Err(Error::VariantMismatch(detected))
         }
     }
 
@@ -530,6 +1383,16 @@ impl<const X: usize> BQ769x0<X> where [(); X * 2]: Sized, [(); X * 4]: Sized {
         self.use_crc
     }
 
+    // Number of times a transaction is repeated in CRC mode before its error is returned: reads after a CRC
+    // mismatch, writes after a NACK (the device NACKs a write with bad CRC, reported as I2CError).
+    pub fn set_crc_retries(&mut self, retries: u8) {
+        self.crc_retries = retries;
+    }
+
+    pub fn crc_retries(&self) -> u8 {
+        self.crc_retries
+    }
+
     pub fn adc_gain(&self) -> u16 {
         self.adc_gain
     }
@@ -538,13 +1401,43 @@ impl<const X: usize> BQ769x0<X> where [(); X * 2]: Sized, [(); X * 4]: Sized {
         self.adc_offset
     }
 
+    // Gain and offset as read by read_adc_characteristics (or restored by import_calibration)
+    pub fn adc_characteristics(&self) -> Result<AdcCharacteristics, Error> {
+        if self.adc_gain == 0 {
+            return Err(Error::Uninitialized);
+        }
+        Ok(AdcCharacteristics {
+            gain_uv_per_lsb: self.adc_gain,
+            offset_mv: self.adc_offset
+        })
+    }
+
+    // Factory ADC characteristics, shunt and current offset, to be stored and restored on warm boot
+    pub fn export_calibration(&self) -> Calibration {
+        Calibration {
+            adc_gain: self.adc_gain,
+            adc_offset: self.adc_offset,
+            shunt: self.shunt,
+            current_offset: self.current_offset
+        }
+    }
+
+    // Restores state saved with export_calibration, read_adc_characteristics can be skipped afterwards
+    pub fn import_calibration(&mut self, calibration: &Calibration) {
+        self.adc_gain = calibration.adc_gain;
+        self.adc_offset = calibration.adc_offset;
+        self.shunt = calibration.shunt;
+        self.current_offset = calibration.current_offset;
+    }
+
     fn read_raw_nocrc<I2C>(i2c: &mut I2C, dev_address: u8, reg_address: u8, data: &mut [u8]) -> Result<(), Error>
         where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
     {
-        #[cfg(no_std)] {
-            cortex_m::asm::delay(10000);
+        if data.len() > MAX_READ_LEN {
+            return Err(Error::BufTooLarge);
+        } else if data.is_empty() {
+            return Ok(());
         }
-
         match i2c.write_read(dev_address, &[reg_address], data) {
             Ok(_) => { Ok(()) },
             Err(_) => { Err(Error::I2CError) },
@@ -554,61 +1447,76 @@ impl<const X: usize> BQ769x0<X> where [(); X * 2]: Sized, [(); X * 4]: Sized {
     fn read_raw_crc<I2C>(i2c: &mut I2C, dev_address: u8, reg_address: u8, data: &mut [u8]) -> Result<(), Error>
         where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
     {
-        if data.len() > X * 2 { // max 5/10/15 cell voltages * 2 bytes
+        if data.len() > MAX_READ_LEN {
             return Err(Error::BufTooLarge);
-        } else if data.len() == 0 {
+        } else if data.is_empty() {
             return Ok(());
         }
-        let mut buf = [0u8; X * 4]; // byte,crc,byte,crc,...
-        let r = i2c.write_read(dev_address, &[reg_address], &mut buf[0..data.len()*2]);
+        // byte,crc,byte,crc,... data.len() <= MAX_READ_LEN, so the largest read (40 bytes) fits as well as 15 cells
+        let mut buf = [0u8; MAX_READ_LEN * 2];
+        let buf = &mut buf[..data.len() * 2];
+        i2c.write_read(dev_address, &[reg_address], buf).map_err(|_| Error::I2CError)?;
         let mut crc = CRCu8::crc8();
-        crc.reset();
-        crc.digest(&[(dev_address << 1) | 0b0000_0001, buf[0]]);
-        if crc.get_crc() != buf[1] {
-            return Err(Error::CRCMismatch);
-        }
-        if data.len() > 1 {
-            for i in (3..data.len()*2).step_by(2) {
-                crc.reset();
-                crc.digest(&[buf[i - 1]]);
-                if crc.get_crc() != buf[i] {
-                    return Err(Error::CRCMismatch);
-                }
+        for (i, pair) in buf.chunks_exact(2).enumerate() {
+            crc.reset();
+            if i == 0 {
+                // first CRC also covers the slave address
+                crc.digest(&[(dev_address << 1) | 0b0000_0001]);
             }
-        }
-        return if r.is_ok() {
-            for (i, b) in data.iter_mut().enumerate() {
-                *b = buf[i * 2];
+            crc.digest(&[pair[0]]);
+            if crc.get_crc() != pair[1] {
+                return Err(Error::CRCMismatch);
             }
-            Ok(())
-        } else {
-            Err(Error::I2CError)
         }
+        for (b, pair) in data.iter_mut().zip(buf.chunks_exact(2)) {
+            *b = pair[0];
+        }
+        Ok(())
     }
 
     pub fn read_raw<I2C>(&mut self, i2c: &mut I2C, reg_address: u8, data: &mut [u8]) -> Result<(), Error>
         where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
     {
         if self.use_crc {
-            Self::read_raw_crc(i2c, self.dev_address, reg_address, data)
+            let mut retries = self.crc_retries;
+            loop {
+                match Self::read_raw_crc(i2c, self.dev_address, reg_address, data) {
+                    Err(Error::CRCMismatch) if retries > 0 => retries -= 1,
+                    r => return r
+                }
+            }
         } else {
             Self::read_raw_nocrc(i2c, self.dev_address, reg_address, data)
         }
     }
 
-    fn write_raw_nocrc<I2C>(i2c: &mut I2C, dev_address: u8, reg_address: u8, data: &[u8]) -> Result<(), Error>
+    // Single register read, CRC checked if enabled
+    pub fn read_u8<I2C>(&mut self, i2c: &mut I2C, reg_address: u8) -> Result<u8, Error>
         where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
     {
-        #[cfg(no_std)] {
-            cortex_m::asm::delay(10000);
-        }
+        let mut data = [0u8; 1];
+        self.read_raw(i2c, reg_address, &mut data)?;
+        Ok(data[0])
+    }
+
+    // reg_address is the _HI register, reg_address + 1 the _LO one
+    pub fn read_u16_be<I2C>(&mut self, i2c: &mut I2C, reg_address: u8) -> Result<u16, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        let mut data = [0u8; 2];
+        self.read_raw(i2c, reg_address, &mut data)?;
+        Ok(u16::from_be_bytes(data))
+    }
 
-        if data.len() > 8 {
+    fn write_raw_nocrc<I2C>(i2c: &mut I2C, dev_address: u8, reg_address: u8, data: &[u8]) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        if data.len() > MAX_WRITE_LEN {
             return Err(Error::BufTooLarge);
-        } else if data.len() == 0 {
+        } else if data.is_empty() {
             return Ok(());
         }
-        let mut buf = [0u8; 8+1]; // reg,byte,byte,...
+        let mut buf = [0u8; MAX_WRITE_LEN + 1]; // reg,byte,byte,...
         buf[0] = reg_address;
         for (i, b) in data.iter().enumerate() {
             buf[i + 1] = *b;
@@ -627,16 +1535,12 @@ impl<const X: usize> BQ769x0<X> where [(); X * 2]: Sized, [(); X * 4]: Sized {
     fn write_raw_crc<I2C>(i2c: &mut I2C, dev_address: u8, reg_address: u8, data: &[u8]) -> Result<(), Error>
         where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
     {
-        //#[cfg(no_std)] {
-            cortex_m::asm::delay(10000);
-        //}
-
-        if data.len() > 8 {
+        if data.len() > MAX_WRITE_LEN {
             return Err(Error::BufTooLarge);
-        } else if data.len() == 0 {
+        } else if data.is_empty() {
             return Ok(());
         }
-        let mut buf = [0u8; 8*2+1]; // reg,byte,crc,byte,crc,...
+        let mut buf = [0u8; MAX_WRITE_LEN * 2 + 1]; // reg,byte,crc,byte,crc,...
         buf[0] = reg_address;
         for (i, b) in data.iter().enumerate() {
             buf[i * 2 + 1] = *b;
@@ -658,21 +1562,48 @@ impl<const X: usize> BQ769x0<X> where [(); X * 2]: Sized, [(); X * 4]: Sized {
     pub fn write_raw<I2C>(&mut self, i2c: &mut I2C, reg_address: u8, data: &[u8]) -> Result<(), Error>
         where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
     {
+        // any write covering SYS_CTRL1 may change TEMP_SEL, modify_sys_ctrl1() re-caches it afterwards
+        if (reg_address as usize..reg_address as usize + data.len()).contains(&(regs::SYS_CTRL1 as usize)) {
+            self.temp_source = None;
+        }
         if self.use_crc {
-            Self::write_raw_crc(i2c, self.dev_address, reg_address, data)
+            let mut retries = self.crc_retries;
+            loop {
+                match Self::write_raw_crc(i2c, self.dev_address, reg_address, data) {
+                    Err(Error::I2CError) if retries > 0 => retries -= 1,
+                    r => return r
+                }
+            }
         } else {
             Self::write_raw_nocrc(i2c, self.dev_address, reg_address, data)
         }
     }
 
-    fn read_adc_characteristics<I2C>(&mut self, i2c: &mut I2C) -> Result<(), Error>
+    // write_raw() for any length, split into MAX_WRITE_LEN transactions to consecutive registers (CRC per chunk).
+    // BufTooLarge if the block runs past the end of the register map, nothing is written in that case.
+    pub fn write_block<I2C>(&mut self, i2c: &mut I2C, reg_address: u8, data: &[u8]) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        if reg_address as usize + data.len() > u8::MAX as usize + 1 {
+            return Err(Error::BufTooLarge);
+        }
+        for (i, chunk) in data.chunks(MAX_WRITE_LEN).enumerate() {
+            self.write_raw(i2c, reg_address + (i * MAX_WRITE_LEN) as u8, chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Init stage 1: reads ADC gain and offset from the factory registers.
+    pub fn read_adc_characteristics<I2C>(&mut self, i2c: &mut I2C) -> Result<(), Error>
         where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
     {
         let mut gain1_offset = [0u8; 2];
         let mut gain2 = [0u8; 1];
-        self.read_raw(i2c, 0x50, &mut gain1_offset)?;
-        self.read_raw(i2c, 0x59, &mut gain2)?;
-        self.adc_gain = 365 + ( ((gain1_offset[0] << 1) & 0b0001_1000) | (gain2[0] >> 5) ) as u16;
+        self.read_raw(i2c, regs::ADCGAIN1, &mut gain1_offset)?;
+        self.read_raw(i2c, regs::ADCGAIN2, &mut gain2)?;
+        // ADCGAIN<4:3> in ADCGAIN1 bits 3:2, ADCGAIN<2:0> in ADCGAIN2 bits 7:5
+        let gain_bits = ((gain1_offset[0] as u16 & 0b0000_1100) << 1) | (gain2[0] as u16 >> 5);
+        self.adc_gain = 365 + gain_bits;
         self.adc_offset = gain1_offset[1] as i8;
 
         Ok(())
@@ -688,433 +1619,4672 @@ impl<const X: usize> BQ769x0<X> where [(); X * 2]: Sized, [(); X * 4]: Sized {
         if !self.is_initialized() {
             return Err(Error::Uninitialized);
         }
-        let mut buf = [0u8; X * 2];
-        self.read_raw(i2c, 0x0c, &mut buf)?;
-        let adc_tf = self.adc_transfer_function();
-        for (i, cell) in self.cells.iter_mut().enumerate() {
-            let adc_reading = ((buf[i * 2] as u16) << 8) | buf[i * 2 + 1] as u16;
-            *cell = adc_tf.apply(adc_reading);
-        }
+        let mut buf = [0u8; CELL_BLOCK_MAX];
+        self.read_raw(i2c, regs::VC1_HI, &mut buf[..X * 2])?;
+        self.decode_cells(&buf);
 
-        let cc = self.cell_count;
+        Ok(&self.cells[..self.cell_count as usize])
+    }
 
-        if cc == 3 || cc == 6 || cc == 9 {
-            self.cells[2] = self.cells[4];
-        } else if cc == 4 || cc == 7 || cc == 8 || cc == 10 || cc == 11 || cc == 12 {
-            self.cells[3] = self.cells[4];
+    // cell_voltages() copied into a caller buffer, so the values can be kept while talking to the chip.
+    // Returns the number of cells written, BufTooSmall(cell_count) if out can not hold them all.
+    pub fn read_cells_into<I2C>(&mut self, i2c: &mut I2C, out: &mut [MilliVolts]) -> Result<usize, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        let cell_count = self.cell_count as usize;
+        if out.len() < cell_count {
+            return Err(Error::BufTooSmall(cell_count));
         }
+        out[..cell_count].copy_from_slice(self.cell_voltages(i2c)?);
+        Ok(cell_count)
+    }
 
-        if (X == BQ76930 || X == BQ76940) && (cc == 6 || cc == 7 || cc == 9 || cc == 10) {
-            self.cells[7] = self.cells[9];
+    // Per-cell mean of `samples` cell_voltages() readings (0 is taken as 1), the result replaces the cached cells
+    pub fn cell_voltages_averaged<I2C>(&mut self, i2c: &mut I2C, samples: u8) -> Result<&[MilliVolts], Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        let samples = samples.max(1);
+        let mut sums = [0u64; X];
+        for _ in 0..samples {
+            let cells = self.cell_voltages(i2c)?;
+            for (sum, mv) in sums.iter_mut().zip(cells.iter()) {
+                *sum += mv.0 as u64;
+            }
         }
+        for (cell, sum) in self.cells.iter_mut().zip(sums.iter()) {
+            *cell = MilliVolts((*sum / samples as u64) as u32);
+        }
+        Ok(&self.cells[..self.cell_count as usize])
+    }
 
-        if (X == BQ76930 || X == BQ76940) && (cc == 8 || cc == 9 || cc == 11 || cc == 12 || cc == 13) {
-            self.cells[8] = self.cells[9];
+    // Reads only VCn_HI/VCn_LO of physical input n (0 = VC1), does not update the cached cells
+    pub fn cell_voltage<I2C>(&mut self, i2c: &mut I2C, n: usize) -> Result<MilliVolts, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        if !self.is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+        if n >= X || !self.connected_cell_map().contains(n) {
+            return Err(Error::InvalidCell(n));
         }
+        let mut buf = [0u8; 2];
+        self.read_raw(i2c, regs::VC1_HI + 2 * n as u8, &mut buf)?;
+        Ok(self.convert_adc(u16::from_be_bytes(buf)))
+    }
 
-        if (X == BQ76940) && (cc == 9 || cc == 10 || cc == 11) {
-            self.cells[12] = self.cells[14];
+    // buf holds VC1_HI..VCX_LO, unused cells are replaced by the connected ones per cell_count
+    fn decode_cells(&mut self, buf: &[u8]) {
+        let adc_tf = self.adc_transfer_function();
+        for (cell, adc_reading) in self.cells.iter_mut().zip(Self::decode_cells_raw(buf).iter()) {
+            *cell = adc_tf.apply(*adc_reading);
         }
+        Self::remap_cells(self.connected_cell_map(), &mut self.cells);
+        for (cell, offset) in self.cells.iter_mut().zip(self.cell_offsets.iter()) {
+            *cell = MilliVolts((cell.0 as i64 + *offset as i64).max(0) as u32);
+        }
+        self.cells_read = true;
+    }
 
-        if (X == BQ76940) && (cc == 12 || cc == 13 || cc == 14) {
-            self.cells[13] = self.cells[14];
+    // Fixed per-cell corrections in mV added after the ADC transfer function, offsets[n] applies to
+    // cell_voltages()[n] (after remapping). Missing entries are 0, BufTooLarge if there are more than X.
+    // cell_voltage() and cell_voltages_physical() are not corrected.
+    pub fn set_cell_offsets(&mut self, offsets: &[i16]) -> Result<(), Error> {
+        if offsets.len() > X {
+            return Err(Error::BufTooLarge);
         }
+        self.cell_offsets = [0; X];
+        self.cell_offsets[..offsets.len()].copy_from_slice(offsets);
+        Ok(())
+    }
 
-        Ok(&self.cells[..self.cell_count as usize])
+    pub fn cell_offsets(&self) -> &[i16; X] {
+        &self.cell_offsets
     }
 
-    pub fn enable_balancing<I2C>(&mut self, i2c: &mut I2C, cells: u8) -> Result<(), Error>
-        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
-    {
-        self.write_raw(i2c, 0x01, &[cells])
+    fn decode_cells_raw(buf: &[u8]) -> [u16; X] {
+        let mut raw = [0u16; X];
+        for (i, adc_reading) in raw.iter_mut().enumerate() {
+            *adc_reading = ((buf[i * 2] as u16) << 8) | buf[i * 2 + 1] as u16;
+        }
+        raw
     }
 
-    pub fn balancing_state<I2C>(&mut self, i2c: &mut I2C) -> Result<u8, Error>
-        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
-    {
-        let mut data = [0u8; 1];
-        self.read_raw(i2c, 0x01, &mut data)?;
-        Ok(data[0])
+    // Moves connected channels to the front, in order, so the first cell_count entries are the real cells
+    fn remap_cells<T: Copy>(connected: CellMask, cells: &mut [T; X]) {
+        for (logical, physical) in connected.iter().enumerate() {
+            cells[logical] = cells[physical];
+        }
     }
 
-    pub fn current<I2C>(&mut self, i2c: &mut I2C) -> Result<MilliAmperes, Error>
+    // Every VC input converted as is, index n = VC(n+1), without remapping and without updating the cached cells.
+    // Lets diagnostics see unused and dead channels that cell_voltages() leaves out.
+    pub fn cell_voltages_physical<I2C>(&mut self, i2c: &mut I2C) -> Result<[MilliVolts; X], Error>
         where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
     {
-        // let mut sys_ctrl2 = [0u8; 1];
-        // self.read_raw(i2c, 0x05, &mut sys_ctrl2)?;
-        // sys_ctrl2[0] = sys_ctrl2[0] | 0b0010_0000;
-        // self.write_raw(i2c, 0x05, &sys_ctrl2)?;
-        // delay(8_000_000);
-        let mut cc = [0u8; 2];
-        self.read_raw(i2c, 0x32, &mut cc)?;
-        let cc = i16::from_be_bytes(cc);
-        let vshunt = cc as i32 * 8440; // nV
-        let current = vshunt / self.shunt.0 as i32;
-        Ok(MilliAmperes(current))
+        if !self.is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+        let mut buf = [0u8; CELL_BLOCK_MAX];
+        self.read_raw(i2c, regs::VC1_HI, &mut buf[..X * 2])?;
+        let adc_tf = self.adc_transfer_function();
+        let mut cells = [MilliVolts(0); X];
+        for (cell, adc_reading) in cells.iter_mut().zip(Self::decode_cells_raw(&buf).iter()) {
+            *cell = adc_tf.apply(*adc_reading);
+        }
+        Ok(cells)
     }
 
-    pub fn voltage<I2C>(&mut self, i2c: &mut I2C) -> Result<MilliVolts, Error>
+    // Unconverted 14-bit ADC codes, remapped the same way as cell_voltages(): first cell_count entries are valid
+    pub fn cell_voltages_raw<I2C>(&mut self, i2c: &mut I2C) -> Result<[u16; X], Error>
         where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
     {
-        // let mut sys_ctrl2 = [0u8; 1];
-        // self.read_raw(i2c, 0x05, &mut sys_ctrl2)?;
-        // sys_ctrl2[0] = sys_ctrl2[0] | 0b0010_0000;
-        // self.write_raw(i2c, 0x05, &sys_ctrl2)?;
-        // delay(8_000_000);
-        let mut vv = [0u8; 2];
-        self.read_raw(i2c, 0x2a, &mut vv)?;
-        let vv = u16::from_be_bytes(vv);
-        let voltage = 4 * (self.adc_gain as i32) * (vv as i32) + 5 * (self.adc_offset as i32) * 1000;
-        Ok(MilliVolts((voltage / 1000) as u32))
+        let mut buf = [0u8; CELL_BLOCK_MAX];
+        self.read_raw(i2c, regs::VC1_HI, &mut buf[..X * 2])?;
+        let mut raw = Self::decode_cells_raw(&buf);
+        Self::remap_cells(self.connected_cell_map(), &mut raw);
+        Ok(raw)
     }
 
-    pub fn temperature<I2C>(&mut self, i2c: &mut I2C) -> Result<Temperature, Error>
-        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
-    {
-        let mut ts = [0u8; 2];
-        self.read_raw(i2c, 0x2c, &mut ts)?;
-        let ts = u16::from_be_bytes(ts);
-        let vtsx = (ts as i32) * 382; // µV/LSB
-        match self.temperature_source(i2c)? {
-            TemperatureSource::InternalDie => {
+    // Bit n set if VC(n+1) input measures a real cell. Cells are spread over the 5-channel groups with the
+    // lower groups taking the extra ones; a group with 3 cells uses VC1, VC2, VC5, with 4 cells VC1, VC2, VC3, VC5.
+    // cell_voltages() returns these channels in ascending order, e.g. 8 cells on BQ76930 -> VC1-3, VC5, VC6-8, VC10.
+    pub fn connected_cell_map(&self) -> CellMask {
+        if let CellWiring::Custom(map) = self.cell_wiring {
+            return map;
+        }
+        let groups = X / 5;
+        let mut map = 0u16;
+        for group in 0..groups {
+            let cells_in_group = self.cell_count as usize / groups + (group < self.cell_count as usize % groups) as usize;
+            let group_map = match cells_in_group {
+                3 => 0b1_0011,
+                4 => 0b1_0111,
+                _ => 0b1_1111,
+            };
+            map |= group_map << (group * 5);
+        }
+        CellMask(map)
+    }
 
-                Ok(Temperature::InternalDie(DegreesCentigrade(vtsx)))
+    // Selects which VC inputs are used when the cell count allows more than one arrangement (e.g. 4+5 instead
+    // of 5+4 for 9 cells on BQ76930). Every group needs VC1, VC2 and VC5 of the group connected and 3 to 5 cells total.
+    pub fn set_cell_wiring(&mut self, wiring: CellWiring) -> Result<(), Error> {
+        if let CellWiring::Custom(map) = wiring {
+            let channels = (1u16 << X) - 1;
+            if map.bits() & !channels != 0 || map.bits().count_ones() != self.cell_count as u32 {
+                return Err(Error::InvalidCellWiring);
             }
-            TemperatureSource::ExternalThermistor => {
-
-                Ok(Temperature::ExternalThermistor(DegreesCentigrade(vtsx)))
+            for group in 0..X / 5 {
+                match (map.bits() >> (group * 5)) & 0b1_1111 {
+                    0b1_0011 | 0b1_0111 | 0b1_1111 => {},
+                    _ => return Err(Error::InvalidCellWiring)
+                }
             }
         }
-        // match source {
-        //     TemperatureSource::InternalDie => {
-        //         let v25 = 1200000; // µV at 25degC
-        //         let t = 25 - ((vtsx - v25) * 238);
-        //         Ok(DegreesCentigrade( t as i16 ))
-        //     }
-        //     TemperatureSource::ExternalThermistor => {
-        //         // let rts = (10_000 * vtsx)
-        //         Ok(DegreesCentigrade(0))
-        //     }
-        // }
+        self.cell_wiring = wiring;
+        self.cells_read = false;
+        Ok(())
     }
 
-    pub fn sys_stat<I2C>(&mut self, i2c: &mut I2C) -> Result<Stat, Error>
-        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
-    {
-        let mut data = [0u8; 1];
-        self.read_raw(i2c, 0x00, &mut data)?;
-        Ok(Stat{ bits: data[0] })
+    pub fn cell_wiring(&self) -> CellWiring {
+        self.cell_wiring
     }
 
-    pub fn sys_stat_reset<I2C>(&mut self, i2c: &mut I2C, flags: SysStat) -> Result<(), Error>
-        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
-    {
-        self.write_raw(i2c, 0x00, &[flags.bits()])
+    // Cell count given to new()
+    pub fn cell_count(&self) -> u8 {
+        self.cell_count
     }
 
-    pub fn discharge<I2C>(&mut self, i2c: &mut I2C, enable: bool) -> Result<(), Error>
-        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
-    {
-        let mut sys_ctrl2 = [0u8; 1];
-        self.read_raw(i2c, 0x05, &mut sys_ctrl2)?;
-        let already_enabled = sys_ctrl2[0] & 0b0000_0010 != 0;
-        if enable == already_enabled {
-            return Ok(())
+    // (physical cell index, voltage) of connected cells from the last cell_voltages() call
+    pub fn cells_iter(&self) -> impl Iterator<Item=(usize, MilliVolts)> + '_ {
+        let connected = if self.cells_read { self.connected_cell_map() } else { CellMask(0) };
+        connected.iter().zip(self.cells.iter().copied())
+    }
+
+    // Voltage of the physical cell index from the last cell_voltages() call, None if not connected
+    pub fn cell(&self, index: usize) -> Option<MilliVolts> {
+        let connected = self.connected_cell_map();
+        if !self.cells_read || index >= X || !connected.contains(index) {
+            return None;
         }
-        if enable {
-            sys_ctrl2[0] = sys_ctrl2[0] | 0b0000_0010;
-        } else {
-            sys_ctrl2[0] = sys_ctrl2[0] & !0b0000_0010;
+        let logical = (connected.bits() & ((1 << index) - 1)).count_ones() as usize;
+        Some(self.cells[logical])
+    }
+
+    fn last_cells(&self) -> Result<&[MilliVolts], Error> {
+        if !self.cells_read {
+            return Err(Error::Uninitialized);
         }
-        self.write_raw(i2c, 0x05, &sys_ctrl2)
+        Ok(&self.cells[..self.cell_count as usize])
     }
 
-    pub fn charge<I2C>(&mut self, i2c: &mut I2C, enable: bool) -> Result<(), Error>
+    // How far each cell of the last cell_voltages() reading is above target, 0 for cells at or below it.
+    // Same order as cell_voltages(), entries past cell_count are 0. With the bleed resistor this gives
+    // the charge to remove per cell.
+    pub fn balance_targets(&self, target: MilliVolts) -> Result<[MilliVolts; X], Error> {
+        let mut excess = [MilliVolts(0); X];
+        for (excess, mv) in excess.iter_mut().zip(self.last_cells()?.iter()) {
+            *excess = *mv - target;
+        }
+        Ok(excess)
+    }
+
+    // min/max/delta work on the values from the last cell_voltages() call
+    pub fn min_cell(&self) -> Result<(usize, MilliVolts), Error> {
+        self.last_cells()?.iter().copied().enumerate()
+            .min_by_key(|(_, mv)| *mv)
+            .ok_or(Error::Uninitialized)
+    }
+
+    pub fn max_cell(&self) -> Result<(usize, MilliVolts), Error> {
+        self.last_cells()?.iter().copied().enumerate()
+            .max_by_key(|(_, mv)| *mv)
+            .ok_or(Error::Uninitialized)
+    }
+
+    pub fn cell_delta(&self) -> Result<MilliVolts, Error> {
+        Ok(self.max_cell()?.1 - self.min_cell()?.1)
+    }
+
+    // SOC in % of each cell from the last cell_voltages() call, looked up in a caller supplied OCV table
+    // (see ocv_to_soc). Entries past cell_count are 0.
+    pub fn cells_soc(&self, table: &[(MilliVolts, u8)]) -> Result<[u8; X], Error> {
+        let mut soc = [0u8; X];
+        for (soc, mv) in soc.iter_mut().zip(self.last_cells()?.iter()) {
+            *soc = ocv_to_soc(table, *mv);
+        }
+        Ok(soc)
+    }
+
+    pub fn cells_sum(&self) -> Result<MilliVolts, Error> {
+        Ok(self.last_cells()?.iter().copied().sum())
+    }
+
+    // Connected inputs whose last reading is below floor or above ceiling, bit n = VC(n+1) like connected_cell_map().
+    // 0mV or rail readings point to an open sense wire or a shorted cell.
+    pub fn validate_cells(&self, floor: MilliVolts, ceiling: MilliVolts) -> Result<CellMask, Error> {
+        if !self.cells_read {
+            return Err(Error::Uninitialized);
+        }
+        let implausible = self.cells_iter()
+            .filter(|(_, mv)| *mv < floor || *mv > ceiling)
+            .fold(0u16, |mask, (i, _)| mask | (1 << i));
+        Ok(CellMask(implausible))
+    }
+
+    // Compares the last cells sum with the BAT register, returns the difference if it exceeds max_discrepancy.
+    // A large mismatch usually means a broken sense wire.
+    pub fn pack_voltage_discrepancy<I2C>(&mut self, i2c: &mut I2C, max_discrepancy: MilliVolts) -> Result<Option<MilliVolts>, Error>
         where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
     {
-        let mut sys_ctrl2 = [0u8; 1];
-        self.read_raw(i2c, 0x05, &mut sys_ctrl2)?;
-        let already_enabled = sys_ctrl2[0] & 0b0000_0001 != 0;
-        if enable == already_enabled {
-            return Ok(())
-        }
-        if enable {
-            sys_ctrl2[0] = sys_ctrl2[0] | 0b0000_0001;
+        let cells_sum = self.cells_sum()?;
+        let bat = self.voltage(i2c)?;
+        let discrepancy = if cells_sum > bat { cells_sum - bat } else { bat - cells_sum };
+        if discrepancy > max_discrepancy {
+            Ok(Some(discrepancy))
         } else {
-            sys_ctrl2[0] = sys_ctrl2[0] & !0b0000_0001;
+            Ok(None)
         }
-        self.write_raw(i2c, 0x05, &sys_ctrl2)
     }
 
-    pub fn is_charge_enabled<I2C>(&mut self, i2c: &mut I2C) -> Result<bool, Error>
+    // Bit n = cell n, written to CELLBAL1 (cells 0-4), CELLBAL2 (5-9) and CELLBAL3 (10-14) as present on the chip
+    pub fn enable_balancing<I2C>(&mut self, i2c: &mut I2C, cells: u16) -> Result<(), Error>
         where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
     {
-        let mut sys_ctrl2 = [0u8; 1];
-        self.read_raw(i2c, 0x05, &mut sys_ctrl2)?;
-        Ok(sys_ctrl2[0] & 0b0000_0001 != 0)
+        if Self::has_adjacent_cells(cells) {
+            return Err(Error::AdjacentBalancing);
+        }
+        let mut cellbal = [0u8; 3];
+        for (group, bits) in cellbal.iter_mut().enumerate() {
+            *bits = ((cells >> (group * 5)) & 0b1_1111) as u8;
+        }
+        self.write_raw(i2c, regs::CELLBAL1, &cellbal[..X / 5])
     }
 
-    pub fn ship_enter<I2C>(&mut self, i2c: &mut I2C) -> Result<(), Error>
+    // enable_balancing() guarded by a watchdog: balance_tick() clears CELLBAL once the cells were on for max_on_ms.
+    // Calling it again before that refreshes the timer, after the timeout balancing stays off until called again.
+    pub fn balance_with_timeout<I2C>(&mut self, i2c: &mut I2C, cells: u16, max_on_ms: u32) -> Result<(), Error>
         where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
     {
-        self.write_raw(i2c, 0x04, &[0b0000_0000])?;
-        self.write_raw(i2c, 0x04, &[0b0000_0001])?;
-        self.write_raw(i2c, 0x04, &[0b0000_0010])?;
+        self.enable_balancing(i2c, cells)?;
+        self.balance_timer = if cells == 0 { None } else { Some((max_on_ms, 0)) };
         Ok(())
     }
 
-    fn adc_transfer_function(&self) -> AdcTransferFunction {
-        AdcTransferFunction {
-            gain: self.adc_gain,
-            offset: self.adc_offset
+    // Advances the balancing watchdog, returns true if it expired and CELLBAL was cleared
+    pub fn balance_tick<I2C>(&mut self, i2c: &mut I2C, elapsed_ms: u32) -> Result<bool, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        let (max_on_ms, on_ms) = match self.balance_timer {
+            Some(timer) => timer,
+            None => return Ok(false),
+        };
+        let on_ms = on_ms.saturating_add(elapsed_ms);
+        self.balance_timer = Some((max_on_ms, on_ms));
+        if on_ms < max_on_ms {
+            return Ok(false);
         }
+        // timer stays expired if the write fails, so the next tick tries again
+        self.enable_balancing(i2c, 0)?;
+        self.balance_timer = None;
+        Ok(true)
     }
 
-    fn ov_voltage_range(&self) -> (MilliVolts, MilliVolts) {
-        let min_adc_reading = 0b10_0000_0000_1000;
-        let max_adc_reading = 0b10_1111_1111_1000;
-        (self.adc_transfer_function().apply(min_adc_reading), self.adc_transfer_function().apply(max_adc_reading))
-    }
-
-    fn uv_voltage_range(&self) -> (MilliVolts, MilliVolts) {
-        let min_adc_reading = 0b01_0000_0000_0000;
-        let max_adc_reading = 0b01_1111_1111_0000;
-        (self.adc_transfer_function().apply(min_adc_reading), self.adc_transfer_function().apply(max_adc_reading))
+    pub fn balancing_state<I2C>(&mut self, i2c: &mut I2C) -> Result<u8, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        self.read_u8(i2c, regs::CELLBAL1)
     }
 
-    pub fn init<I2C>(&mut self, i2c: &mut I2C, config: &Config) -> Result<CalculatedValues, Error>
+    // All CELLBAL registers of the chip, bit n = cell n
+    pub fn balancing_cells<I2C>(&mut self, i2c: &mut I2C) -> Result<CellMask, Error>
         where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
     {
-        self.read_adc_characteristics(i2c)?;
-
-        let scd_threshold = SCDThreshold::from_current(config.scd_threshold, config.shunt);
-        let ocd_threshold = OCDThreshold::from_current(config.ocd_threshold, config.shunt);
-        let scd_range = scd_threshold.range();
-        let ocd_range = ocd_threshold.range();
-        if (scd_range == OCDSCDRange::Lower && ocd_range == OCDSCDRange::Upper) ||
-            (scd_range == OCDSCDRange::Upper && ocd_range == OCDSCDRange::Lower) {
-            return Err(Error::OCDSCDRangeMismatch);
+        let mut cellbal = [0u8; 3];
+        self.read_raw(i2c, regs::CELLBAL1, &mut cellbal[..X / 5])?;
+        let mut mask = 0u16;
+        for (group, bits) in cellbal.iter().enumerate() {
+            mask |= ((bits & 0b1_1111) as u16) << (group * 5);
         }
-        let range_to_use = if scd_range == OCDSCDRange::Unknown {
-            if ocd_range == OCDSCDRange::Unknown {
-                OCDSCDRange::Lower
-            } else {
-                ocd_range
-            }
-        } else if ocd_range == OCDSCDRange::Unknown {
-            if scd_range == OCDSCDRange::Unknown {
-                OCDSCDRange::Lower
-            } else {
-                scd_range
-            }
-        } else {
-            ocd_range // both ranges are equal
-        };
-        let scd_bits = scd_threshold.bits(range_to_use.clone());
-        let ocd_bits = ocd_threshold.bits(range_to_use.clone());
+        Ok(CellMask(mask))
+    }
 
-        let mut regs = [0u8; 6];
-        regs[0] = range_to_use.bits() | config.scd_delay.bits() | scd_bits; // PROTECT1 (0x06)
-        regs[1] = config.ocd_delay.bits() | ocd_bits; // PROTECT2 (0x07)
-        regs[2] = config.uv_delay.bits() | config.ov_delay.bits(); // PROTECT3 (0x08)
+    /// True if two neighbouring cells of the same 5-cell group are set in the mask.
+    pub fn has_adjacent_cells(mask: u16) -> bool {
+        (1..X).any(|cell| cell % 5 != 0 && mask & (0b11 << (cell - 1)) == (0b11 << (cell - 1)))
+    }
 
-        let ov_limits = self.ov_voltage_range();
-        if !(config.ov_threshold >= ov_limits.0 && config.ov_threshold <= ov_limits.1) {
-            return Err(Error::OVThresholdUnobtainable(ov_limits.0, ov_limits.1));
+    /// Drops cells that would balance simultaneously with an adjacent cell of the same 5-cell group.
+    /// Bit n of the mask is cell n (CELLBAL1 bit 0 = cell 0, CELLBAL2 bit 0 = cell 5, ...).
+    pub fn legal_balancing_mask(requested: u16) -> u16 {
+        let mut legal = 0u16;
+        for cell in 0..X {
+            if requested & (1 << cell) == 0 {
+                continue;
+            }
+            let adjacent_enabled = cell % 5 != 0 && legal & (1 << (cell - 1)) != 0;
+            if !adjacent_enabled {
+                legal |= 1 << cell;
+            }
         }
-        let ov_trip_full = ((config.ov_threshold.0 as i32 - self.adc_offset as i32) * 1000) / self.adc_gain as i32; // ADC value * 1000
-        let ov_bits = (((ov_trip_full as u16) >> 4) & 0xff) as u8;
+        legal
+    }
 
-        let uv_limits = self.uv_voltage_range();
-        if !(config.uv_threshold >= uv_limits.0 && config.uv_threshold <= uv_limits.1) {
-            return Err(Error::UVThresholdUnobtainable(uv_limits.0, uv_limits.1));
+    // Balances the given cells minus the adjacent ones legal_balancing_mask() drops, returns the mask written.
+    // InvalidCell for the first index past the chip's inputs, nothing is written then.
+    pub fn balance_cells<I2C>(&mut self, i2c: &mut I2C, target: &[usize]) -> Result<u16, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        let mut requested = 0u16;
+        for &cell in target.iter() {
+            if cell >= X {
+                return Err(Error::InvalidCell(cell));
+            }
+            requested |= 1 << cell;
         }
-        let uv_trip_full = ((config.uv_threshold.0 as i32 - self.adc_offset as i32) * 1000) / self.adc_gain as i32; // ADC value * 1000
-        let uv_bits = (((uv_trip_full as u16) >> 4) & 0xff) as u8;
-
-        regs[3] = ov_bits; // (0x09)
-        regs[4] = uv_bits; // (0xA)
-        regs[5] = 0x19; // (0xB)
-
-        self.write_raw(i2c, 0x06, &regs)?;
-        self.shunt = config.shunt;
-        self.init_complete = true;
-
-        let mut sysctrl2 = [0u8; 1];
-        self.read_raw(i2c, 0x05, &mut sysctrl2)?;
-        sysctrl2[0] = sysctrl2[0] | 0b0100_0000; // !!CC_EN!!
-        self.write_raw(i2c, 0x05, &sysctrl2)?;
+        let legal = Self::legal_balancing_mask(requested);
+        self.enable_balancing(i2c, legal)?;
+        Ok(legal)
+    }
 
-        Ok(CalculatedValues{
-            ocdscd_range_used: range_to_use,
-            scd_threshold: Amperes(((scd_threshold as u32) * 1000) / config.shunt.0),
-            ocd_threshold: Amperes(((ocd_threshold as u32) * 1000) / config.shunt.0),
-            uv_threshold: self.adc_transfer_function().apply(0b01_0000_0000_0000 | ((uv_bits as u16) << 4)),
-            ov_threshold: self.adc_transfer_function().apply(0b10_0000_0000_1000 | ((ov_bits as u16) << 4))
-        })
+    pub fn current<I2C>(&mut self, i2c: &mut I2C) -> Result<MilliAmperes, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        // let mut sys_ctrl2 = [0u8; 1];
+        // self.read_raw(i2c, 0x05, &mut sys_ctrl2)?;
+        // sys_ctrl2[0] = sys_ctrl2[0] | 0b0010_0000;
+        // self.write_raw(i2c, 0x05, &sys_ctrl2)?;
+        // delay(8_000_000);
+        if !self.is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+        self.check_shunt()?;
+        let mut cc = [0u8; 2];
+        self.read_raw(i2c, regs::CC_HI, &mut cc)?;
+        Ok(self.decode_current(cc))
     }
 
-    pub fn enable_adc<I2C>(&mut self, i2c: &mut I2C, enable: bool) -> Result<(), Error>
+    // current() rounded to whole amperes, same sign as current() (charge positive unless set_current_polarity changed it)
+    pub fn current_amperes<I2C>(&mut self, i2c: &mut I2C) -> Result<SignedAmperes, Error>
         where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
     {
-        let mut sysctrl1 = [0u8; 1];
-        self.read_raw(i2c, 0x04, &mut sysctrl1)?;
-        sysctrl1[0] = sysctrl1[0] & !(1 << 4);
-        sysctrl1[0] = sysctrl1[0] | ((enable as u8) << 4);
-        self.write_raw(i2c, 0x04, &sysctrl1)
+        Ok(self.current(i2c)?.to_amperes())
     }
 
-    pub fn set_temperature_source<I2C>(&mut self, i2c: &mut I2C, source: TemperatureSource) -> Result<(), Error>
+    // Unconverted signed CC_HI/CC_LO count (8.44uV/LSB nominal), no polarity or offset correction
+    pub fn coulomb_counter_raw<I2C>(&mut self, i2c: &mut I2C) -> Result<i16, Error>
         where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
     {
-        let mut sysctrl1 = [0u8; 1];
-        self.read_raw(i2c, 0x04, &mut sysctrl1)?;
-        sysctrl1[0] = sysctrl1[0] & !(1 << 3);
-        let is_external = source == TemperatureSource::ExternalThermistor;
-        sysctrl1[0] = sysctrl1[0] | ((is_external as u8) << 3);
-        self.write_raw(i2c, 0x04, &sysctrl1)
+        Ok(self.read_u16_be(i2c, regs::CC_HI)? as i16)
     }
 
-    pub fn temperature_source<I2C>(&mut self, i2c: &mut I2C) -> Result<TemperatureSource, Error>
+    // For OneShot mode: waits for CC_READY (polling every CC_POLL_INTERVAL_MS for up to timeout_ms), reads CC
+    // and clears the flag. CC_SAMPLE_PERIOD_MS is enough for one conversion.
+    pub fn current_when_ready<I2C, D>(&mut self, i2c: &mut I2C, delay: &mut D, timeout_ms: u32) -> Result<MilliAmperes, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead,
+              D: embedded_hal::blocking::delay::DelayMs<u16>
+    {
+        if !self.is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+        self.check_shunt()?;
+        let cc = self.cc_when_ready(i2c, delay, timeout_ms)?;
+        Ok(self.decode_current(cc))
+    }
+
+    // Continuous CC mode: if CC_READY is set reads the sample into acc and clears the flag, returns whether a
+    // sample was taken. Has to be called more often than every CC_SAMPLE_PERIOD_MS.
+    pub fn accumulate_charge<I2C>(&mut self, i2c: &mut I2C, acc: &mut ChargeAccumulator) -> Result<bool, Error>
         where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
     {
-        let mut sysctrl1 = [0u8; 1];
-        self.read_raw(i2c, 0x04, &mut sysctrl1)?;
-        sysctrl1[0] = sysctrl1[0] & !(1 << 3);
-        let is_external = sysctrl1[0] & (1 << 3) != 0;
-        if is_external {
-            Ok(TemperatureSource::ExternalThermistor)
+        if !self.is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+        self.check_shunt()?;
+        match self.cc_ready(i2c)? {
+            Some(cc) => {
+                acc.add(self.decode_current(cc));
+                Ok(true)
+            }
+            None => Ok(false)
+        }
+    }
+
+    // Shunt is only known after init (or import_calibration), current can't be computed before that
+    fn check_shunt(&self) -> Result<(), Error> {
+        if self.shunt.0 == 0 {
+            Err(Error::Uninitialized)
         } else {
-            Ok(TemperatureSource::InternalDie)
+            Ok(())
         }
     }
 
-        pub fn coulomb_counter_mode<I2C>(&mut self, i2c: &mut I2C, mode: CoulombCounterMode) -> Result<(), Error>
+    // CC reading if CC_READY is set (the flag is cleared), None otherwise
+    fn cc_ready<I2C>(&mut self, i2c: &mut I2C) -> Result<Option<[u8; 2]>, Error>
         where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
     {
-        let mut sysctrl2 = [0u8; 1];
-        self.read_raw(i2c, 0x05, &mut sysctrl2)?;
-        sysctrl2[0] = sysctrl2[0] & !0b0110_0000;
-        match mode {
-            CoulombCounterMode::Disabled => {},
-            CoulombCounterMode::OneShot => { sysctrl2[0] = sysctrl2[0] | (1 << 5); }
-            CoulombCounterMode::Continuous => { sysctrl2[0] = sysctrl2[0] | (1 << 6); }
+        if !self.sys_stat(i2c)?.contains(SysStat::CC_READY) {
+            return Ok(None);
         }
-        self.write_raw(i2c, 0x05, &sysctrl2)
+        let mut cc = [0u8; 2];
+        self.read_raw(i2c, regs::CC_HI, &mut cc)?;
+        self.sys_stat_reset(i2c, SysStat::CC_READY)?;
+        Ok(Some(cc))
     }
-}
 
-#[derive(Copy, Clone)]
-struct AdcTransferFunction {
-    gain: u16,
-    offset: i8
-}
-impl AdcTransferFunction {
-    fn apply(&self, adc_reading: u16) -> MilliVolts {
-        let adc_reading = adc_reading as i32;
-        let uv = adc_reading * self.gain as i32 + self.offset as i32 * 1000;
-        MilliVolts((uv / 1000) as u32)
+    fn cc_when_ready<I2C, D>(&mut self, i2c: &mut I2C, delay: &mut D, timeout_ms: u32) -> Result<[u8; 2], Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead,
+              D: embedded_hal::blocking::delay::DelayMs<u16>
+    {
+        let mut waited_ms = 0;
+        loop {
+            if let Some(cc) = self.cc_ready(i2c)? {
+                return Ok(cc);
+            }
+            if waited_ms >= timeout_ms {
+                return Err(Error::Timeout);
+            }
+            delay.delay_ms(CC_POLL_INTERVAL_MS);
+            waited_ms += CC_POLL_INTERVAL_MS as u32;
+        }
     }
-}
 
-pub enum CoulombCounterMode {
-    Disabled,
-    OneShot,
-    Continuous
-}
+    // Offset is removed in the CC's own sign, before the polarity is applied
+    fn decode_current(&self, cc: [u8; 2]) -> MilliAmperes {
+        let current = self.decode_current_raw(cc).0 - self.current_offset.0;
+        match self.current_polarity {
+            CurrentPolarity::ChargePositive => MilliAmperes(current),
+            CurrentPolarity::DischargePositive => MilliAmperes(-current),
+        }
+    }
 
-#[derive(Eq, PartialEq, Copy, Clone)]
-pub enum TemperatureSource {
-    InternalDie,
-    ExternalThermistor
-}
+    // Current with the sign of the CC reading (positive while charging with the reference wiring), no offset
+    fn decode_current_raw(&self, cc: [u8; 2]) -> MilliAmperes {
+        let cc = i16::from_be_bytes(cc);
+        let vshunt = cc as i64 * self.cc_lsb_nv as i64; // nV
+        MilliAmperes((vshunt / self.shunt.0 as i64) as i32)
+    }
 
-#[derive(Eq, PartialEq, Copy, Clone)]
-pub enum Temperature {
-    InternalDie(DegreesCentigrade),
-    ExternalThermistor(DegreesCentigrade)
-}
+    // Call with no load connected: averages `samples` fresh CC readings (each waiting for CC_READY for up to
+    // timeout_ms, as in current_when_ready) and subtracts the result from all subsequent current readings.
+    // The offset is kept in the CC's sign, so it stays valid when set_current_polarity() changes later.
+    // ZeroSamples if samples is 0, the previous offset is kept.
+    pub fn calibrate_current_offset<I2C, D>(&mut self, i2c: &mut I2C, delay: &mut D, samples: u8, timeout_ms: u32) -> Result<MilliAmperes, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead,
+              D: embedded_hal::blocking::delay::DelayMs<u16>
+    {
+        self.check_shunt()?;
+        if samples == 0 {
+            return Err(Error::ZeroSamples);
+        }
+        let mut sum = 0i32;
+        for _ in 0..samples {
+            let cc = self.cc_when_ready(i2c, delay, timeout_ms)?;
+            sum += self.decode_current_raw(cc).0;
+        }
+        self.current_offset = MilliAmperes(sum / samples as i32);
+        Ok(self.current_offset)
+    }
 
-#[cfg(test)]
-mod tests {
-    extern crate std;
+    pub fn current_offset(&self) -> MilliAmperes {
+        self.current_offset
+    }
 
-    struct DummyI2C {
-        pub regs: [u8; 255],
+    pub fn shunt(&self) -> MicroOhms {
+        self.shunt
     }
 
-    impl DummyI2C {
-        pub fn new() -> Self {
-            let mut regs = [0u8; 255];
-            regs[0x50] = 0x15;
-            regs[0x51] = 0x2b;
-            regs[0x59] = 0xa3;
-            DummyI2C { regs }
+    // Corrects current math for a better characterized sense resistor. SCD/OCD thresholds programmed by init()
+    // are voltages across the shunt and are not re-programmed, their current values shift accordingly.
+    pub fn set_shunt(&mut self, shunt: MicroOhms) -> Result<(), Error> {
+        if shunt.0 == 0 {
+            return Err(Error::ZeroShunt);
         }
+        self.shunt = shunt;
+        Ok(())
     }
 
-    impl embedded_hal::blocking::i2c::Write for DummyI2C {
-        type Error = ();
+    // Per-board trim of the CC resolution in nV per LSB (CC_LSB_NV nominal), scales current() proportionally
+    pub fn set_cc_lsb(&mut self, nv_per_lsb: u32) {
+        self.cc_lsb_nv = nv_per_lsb;
+    }
 
-        fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
-            std::println!("-----------");
-            std::println!("write: {:#04x}", addr);
-            let base_reg_addr = bytes[0] as usize;
-            for (i, b) in bytes.iter().skip(1).enumerate() {
-                let reg_addr = base_reg_addr + i;
-                self.regs[reg_addr] = *b;
-                std::println!("{}/{:#04x}\t<= {:#04x}={:#010b}", reg_addr, reg_addr, *b, *b);
-            }
+    pub fn cc_lsb(&self) -> u32 {
+        self.cc_lsb_nv
+    }
 
-            Ok(())
-        }
+    // Restore a previously measured offset, e.g. from persistent storage. In the CC's sign, independent of
+    // the current polarity.
+    pub fn set_current_offset(&mut self, offset: MilliAmperes) {
+        self.current_offset = offset;
     }
 
-    impl embedded_hal::blocking::i2c::WriteRead for DummyI2C {
-        type Error = ();
+    pub fn set_current_polarity(&mut self, polarity: CurrentPolarity) {
+        self.current_polarity = polarity;
+    }
 
-        fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
-            std::println!("----------------");
-            std::println!("write_read: {:#04x}", address);
-            let base_reg_addr = bytes[0] as usize;
-            for (i, b) in buffer.iter_mut().enumerate() {
-                let reg_addr = base_reg_addr + i;
-                let reg_value = self.regs[reg_addr];
-                *b = reg_value;
-                std::println!("{}/{:#04x}\t== {:#04x}={:#010b}", reg_addr, reg_addr, reg_value, reg_value);
-            }
+    pub fn current_polarity(&self) -> CurrentPolarity {
+        self.current_polarity
+    }
 
-            Ok(())
+    pub fn voltage<I2C>(&mut self, i2c: &mut I2C) -> Result<MilliVolts, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        // let mut sys_ctrl2 = [0u8; 1];
+        // self.read_raw(i2c, 0x05, &mut sys_ctrl2)?;
+        // sys_ctrl2[0] = sys_ctrl2[0] | 0b0010_0000;
+        // self.write_raw(i2c, 0x05, &sys_ctrl2)?;
+        // delay(8_000_000);
+        if !self.is_initialized() {
+            return Err(Error::Uninitialized);
         }
+        let mut vv = [0u8; 2];
+        self.read_raw(i2c, regs::BAT_HI, &mut vv)?;
+        Ok(self.decode_voltage(vv))
     }
 
-    #[test]
-    fn it_works() {
-        use crate::*;
+    fn decode_voltage(&self, vv: [u8; 2]) -> MilliVolts {
+        let vv = u16::from_be_bytes(vv);
+        let voltage = 4 * (self.adc_gain as i32) * (vv as i32) + 5 * (self.adc_offset as i32) * 1000;
+        MilliVolts((voltage / 1000) as u32)
+    }
 
-        let mut i2c = DummyI2C::new();
-        let mut bq769x0 = BQ769x0::new(0x08);
-        let config = Config {
-            shunt: MicroOhms(667),
-            scd_delay: SCDDelay::_400uS,
-            scd_threshold: Amperes(200),
-            ocd_delay: OCDDelay::_1280ms,
-            ocd_threshold: Amperes(100),
-            uv_delay: UVDelay::_4s,
-            uv_threshold: MilliVolts(2000),
-            ov_delay: OVDelay::_4s,
-            ov_threshold: MilliVolts(4175)
+    // Pack power from BAT voltage and CC current, sign follows current()
+    pub fn power<I2C>(&mut self, i2c: &mut I2C) -> Result<MilliWatts, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        let voltage = self.voltage(i2c)?;
+        let current = self.current(i2c)?;
+        Ok(MilliWatts::from_voltage_current(voltage, current))
+    }
+
+    pub fn temperature<I2C>(&mut self, i2c: &mut I2C) -> Result<Temperature, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        if !self.is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+        let mut ts = [0u8; 2];
+        self.read_raw(i2c, regs::TS1_HI, &mut ts)?;
+        let source = self.cached_temperature_source(i2c)?;
+        Ok(Self::decode_temperature(ts, source))
+        // match source {
+        //     TemperatureSource::InternalDie => {
+        //         let v25 = 1200000; // µV at 25degC
+        //         let t = 25 - ((vtsx - v25) * 238);
+        //         Ok(DegreesCentigrade( t as i16 ))
+        //     }
+        //     TemperatureSource::ExternalThermistor => {
+        //         // let rts = (10_000 * vtsx)
+        //         Ok(DegreesCentigrade(0))
+        //     }
+        // }
+    }
+
+    // TS1 on BQ76920, TS1..TS2 on BQ76930, TS1..TS3 on BQ76940, Self::TS_INPUTS readings.
+    // All inputs report either die or thermistor temperature depending on TEMP_SEL.
+    pub fn temperature_all<I2C>(&mut self, i2c: &mut I2C) -> Result<Temperatures, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        if !self.is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+        let mut buf = [0u8; 6];
+        self.read_raw(i2c, regs::TS1_HI, &mut buf[..X / 5 * 2])?;
+        let source = self.cached_temperature_source(i2c)?;
+        let mut temperatures = Temperatures {
+            readings: [Temperature::InternalDie(DegreesCentigrade(0)); 3],
+            len: X / 5
         };
-        match bq769x0.init(&mut i2c, &config) {
-            Ok(actual) => {
-                std::println!("bq769x0 init ok");
-                std::println!("adc gain:{}uV/LSB offset:{}mV", bq769x0.adc_gain(), bq769x0.adc_offset());
-                std::println!("SCD: {}, OCD: {}, range: {:?}", actual.scd_threshold, actual.ocd_threshold, actual.ocdscd_range_used);
-                std::println!("UV: {}, OV: {}", actual.uv_threshold, actual.ov_threshold);
-            }
-            Err(e) => {
-                std::println!("bq769x0 init err: {:?}", e);
+        for (i, t) in temperatures.readings[..X / 5].iter_mut().enumerate() {
+            *t = Self::decode_temperature([buf[i * 2], buf[i * 2 + 1]], source);
+        }
+        Ok(temperatures)
+    }
+
+    // Die sensor: V_25 = 1.2V, -4.2mV/degC. Thermistor readings stay the TS voltage in µV, converting them
+    // needs the thermistor's curve.
+    fn decode_temperature(ts: [u8; 2], source: TemperatureSource) -> Temperature {
+        match source {
+            TemperatureSource::InternalDie => Temperature::InternalDie(Self::decode_die_temperature(ts)),
+            TemperatureSource::ExternalThermistor => {
+                Temperature::ExternalThermistor(DegreesCentigrade(Self::ts_microvolts(ts)))
             }
         }
     }
+
+    fn ts_microvolts(ts: [u8; 2]) -> i32 {
+        (u16::from_be_bytes(ts) as i32) * 382 // µV/LSB
+    }
+
+    fn decode_die_temperature(ts: [u8; 2]) -> DegreesCentigrade {
+        DegreesCentigrade(25 - (Self::ts_microvolts(ts) - 1_200_000) / 4200)
+    }
+
+    // Die temperature from TS1 whatever TEMP_SEL is: with the thermistor selected, switches to the die sensor,
+    // waits TEMP_SOURCE_SWITCH_MS for a conversion and switches back. Thermistor readings are unavailable meanwhile.
+    pub fn die_temperature<I2C, D>(&mut self, i2c: &mut I2C, delay: &mut D) -> Result<DegreesCentigrade, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead,
+              D: embedded_hal::blocking::delay::DelayMs<u16>
+    {
+        if !self.is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+        let was_external = self.read_sys_ctrl1(i2c)?.contains(SysCtrl1::TEMP_SEL);
+        if was_external {
+            self.set_temperature_source(i2c, TemperatureSource::InternalDie)?;
+            delay.delay_ms(TEMP_SOURCE_SWITCH_MS);
+        }
+        let mut ts = [0u8; 2];
+        let read = self.read_raw(i2c, regs::TS1_HI, &mut ts);
+        // restore even if the read failed
+        if was_external {
+            self.set_temperature_source(i2c, TemperatureSource::ExternalThermistor)?;
+        }
+        read?;
+        Ok(Self::decode_die_temperature(ts))
+    }
+
+    // Reads VC1_HI (0x0c) through CC_LO (0x33) in one block, plus TEMP_SEL to interpret TS1 unless already known
+    pub fn read_telemetry<I2C>(&mut self, i2c: &mut I2C) -> Result<Telemetry<X>, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        if !self.is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+        self.check_shunt()?;
+        let source = self.cached_temperature_source(i2c)?;
+        let mut buf = [0u8; TELEMETRY_LEN];
+        self.read_raw(i2c, regs::VC1_HI, &mut buf)?;
+        self.decode_cells(&buf[..X * 2]);
+        Ok(Telemetry {
+            cells: self.cells,
+            cell_count: self.cell_count,
+            voltage: self.decode_voltage(Self::telemetry_pair(&buf, regs::BAT_HI)),
+            temperature: Self::decode_temperature(Self::telemetry_pair(&buf, regs::TS1_HI), source),
+            current: self.decode_current(Self::telemetry_pair(&buf, regs::CC_HI)),
+        })
+    }
+
+    // HI/LO register pair out of a VC1_HI based telemetry block
+    fn telemetry_pair(buf: &[u8; TELEMETRY_LEN], hi: u8) -> [u8; 2] {
+        let offset = (hi - regs::VC1_HI) as usize;
+        [buf[offset], buf[offset + 1]]
+    }
+
+    pub fn sys_stat<I2C>(&mut self, i2c: &mut I2C) -> Result<SysStat, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        Ok(SysStat::from_bits_truncate(self.read_u8(i2c, regs::SYS_STAT)?))
+    }
+
+    pub fn sys_stat_reset<I2C>(&mut self, i2c: &mut I2C, flags: SysStat) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        self.write_raw(i2c, regs::SYS_STAT, &[flags.bits()])
+    }
+
+    // Reads SYS_STAT..CC_LO for field debugging, RegisterDump renders it
+    pub fn dump_registers<I2C>(&mut self, i2c: &mut I2C, out: &mut [u8; REGISTER_DUMP_LEN]) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        for (chunk, data) in out.chunks_mut(MAX_READ_LEN).enumerate() {
+            self.read_raw(i2c, (chunk * MAX_READ_LEN) as u8, data)?;
+        }
+        Ok(())
+    }
+
+    // Active fault flags (everything except CC_READY) as SysStat, so they can be passed back to clear_fault
+    pub fn faults<I2C>(&mut self, i2c: &mut I2C) -> Result<SysStat, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        Ok(self.sys_stat(i2c)? - SysStat::CC_READY)
+    }
+
+    // Faults that appeared since the previous poll (rising edges), latched faults that are still set are not repeated.
+    // A fault that is cleared and comes back is reported again.
+    pub fn poll_faults<I2C>(&mut self, i2c: &mut I2C) -> Result<SysStat, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        let faults = self.faults(i2c)?;
+        let new_faults = faults - self.last_faults;
+        self.last_faults = faults;
+        Ok(new_faults)
+    }
+
+    // DEVICE_XREADY recovery: wait XREADY_RECOVERY_MS, clear the flag and read it back.
+    // Error::XReadyPersistent if it is still set, which points to a real hardware fault.
+    pub fn recover_xready<I2C, D>(&mut self, i2c: &mut I2C, delay: &mut D) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead,
+              D: embedded_hal::blocking::delay::DelayMs<u16>
+    {
+        if !self.sys_stat(i2c)?.contains(SysStat::DEVICE_XREADY) {
+            return Ok(());
+        }
+        // the internal fault may have reset the registers, TEMP_SEL is read again on the next temperature read
+        self.temp_source = None;
+        delay.delay_ms(XREADY_RECOVERY_MS);
+        self.sys_stat_reset(i2c, SysStat::DEVICE_XREADY)?;
+        if self.sys_stat(i2c)?.contains(SysStat::DEVICE_XREADY) {
+            return Err(Error::XReadyPersistent);
+        }
+        Ok(())
+    }
+
+    // Write-1-to-clear of only the given flags, other latched faults stay set
+    pub fn clear_fault<I2C>(&mut self, i2c: &mut I2C, fault: SysStat) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        self.sys_stat_reset(i2c, fault)
+    }
+
+    // OVRD_ALERT is latched by the device when something other than the AFE pulls ALERT high
+    pub fn is_alert_overridden<I2C>(&mut self, i2c: &mut I2C) -> Result<bool, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        Ok(self.sys_stat(i2c)?.contains(SysStat::OVRD_ALERT))
+    }
+
+    // Host-initiated alert: there is no register to set OVRD_ALERT, the host has to drive the shared ALERT line
+    // itself. Returns whether the device latched the override.
+    pub fn assert_alert_override<I2C, P>(&mut self, i2c: &mut I2C, alert_pin: &mut P) -> Result<bool, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead,
+              P: embedded_hal::digital::v2::OutputPin
+    {
+        alert_pin.set_high().map_err(|_| Error::PinError)?;
+        self.is_alert_overridden(i2c)
+    }
+
+    // Releases the ALERT line and clears OVRD_ALERT, it is latched again right away if the line is still held high
+    pub fn clear_alert_override<I2C, P>(&mut self, i2c: &mut I2C, alert_pin: &mut P) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead,
+              P: embedded_hal::digital::v2::OutputPin
+    {
+        alert_pin.set_low().map_err(|_| Error::PinError)?;
+        self.sys_stat_reset(i2c, SysStat::OVRD_ALERT)
+    }
+
+    // Software per-cell lockout on top of the hardware UV trip: with a floor set, discharge(true) and
+    // enable_protection_outputs() refuse with CellBelowFloor while any cell of the last cell_voltages() reading
+    // is below it (Uninitialized without a reading). None disables the check.
+    pub fn set_discharge_floor(&mut self, floor: Option<MilliVolts>) {
+        self.dsg_floor = floor;
+    }
+
+    pub fn discharge_floor(&self) -> Option<MilliVolts> {
+        self.dsg_floor
+    }
+
+    fn check_discharge_floor(&self) -> Result<(), Error> {
+        let floor = match self.dsg_floor {
+            Some(floor) => floor,
+            None => return Ok(()),
+        };
+        if !self.cells_read {
+            return Err(Error::Uninitialized);
+        }
+        match self.cells_iter().find(|(_, mv)| *mv < floor) {
+            Some((cell, mv)) => Err(Error::CellBelowFloor(cell, mv)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn discharge<I2C>(&mut self, i2c: &mut I2C, enable: bool) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        if enable {
+            self.check_discharge_floor()?;
+        }
+        let mut sys_ctrl2 = self.read_sys_ctrl2(i2c)?;
+        if enable == sys_ctrl2.contains(SysCtrl2::DSG_ON) {
+            return Ok(())
+        }
+        sys_ctrl2.set(SysCtrl2::DSG_ON, enable);
+        self.write_raw(i2c, regs::SYS_CTRL2, &[sys_ctrl2.bits()])
+    }
+
+    pub fn charge<I2C>(&mut self, i2c: &mut I2C, enable: bool) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        let mut sys_ctrl2 = self.read_sys_ctrl2(i2c)?;
+        if enable == sys_ctrl2.contains(SysCtrl2::CHG_ON) {
+            return Ok(())
+        }
+        sys_ctrl2.set(SysCtrl2::CHG_ON, enable);
+        self.write_raw(i2c, regs::SYS_CTRL2, &[sys_ctrl2.bits()])
+    }
+
+    // Software OT protection: hottest TS reading above the limit turns off both CHG and DSG.
+    // `thermistor` converts a thermistor TS voltage (µV) to degC, it is not called with the die sensor selected.
+    pub fn check_overtemperature<I2C, F>(&mut self, i2c: &mut I2C, limit: DegreesCentigrade, thermistor: F) -> Result<OvertemperatureStatus, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead,
+              F: Fn(i32) -> DegreesCentigrade
+    {
+        self.check_overtemperature_limits(i2c, OvertemperatureLimits { charge: limit, discharge: limit }, thermistor)
+    }
+
+    // Same as check_overtemperature, but CHG and DSG are turned off independently by their own limits
+    pub fn check_overtemperature_limits<I2C, F>(&mut self, i2c: &mut I2C, limits: OvertemperatureLimits, thermistor: F) -> Result<OvertemperatureStatus, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead,
+              F: Fn(i32) -> DegreesCentigrade
+    {
+        let hottest = self.temperature_all(i2c)?.iter().map(|t| match *t {
+            Temperature::InternalDie(degrees) => degrees,
+            Temperature::ExternalThermistor(DegreesCentigrade(microvolts)) => thermistor(microvolts)
+        }).max().unwrap_or(DegreesCentigrade(0));
+        let status = OvertemperatureStatus {
+            charge: hottest > limits.charge,
+            discharge: hottest > limits.discharge,
+            hottest
+        };
+        if status.charge {
+            self.charge(i2c, false)?;
+        }
+        if status.discharge {
+            self.discharge(i2c, false)?;
+        }
+        Ok(status)
+    }
+
+    // Safe startup after init(): refuses with ActiveFaults if any fault is latched in SYS_STAT, otherwise turns
+    // CHG and DSG on in a single SYS_CTRL2 write.
+    pub fn enable_protection_outputs<I2C>(&mut self, i2c: &mut I2C) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        if !self.is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+        self.check_discharge_floor()?;
+        let faults = self.faults(i2c)?;
+        if !faults.is_empty() {
+            return Err(Error::ActiveFaults(faults));
+        }
+        self.modify_sys_ctrl2(i2c, |flags| flags.insert(SysCtrl2::CHG_ON | SysCtrl2::DSG_ON))?;
+        Ok(())
+    }
+
+    pub fn is_charge_enabled<I2C>(&mut self, i2c: &mut I2C) -> Result<bool, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        Ok(self.read_sys_ctrl2(i2c)?.contains(SysCtrl2::CHG_ON))
+    }
+
+    pub fn is_discharge_enabled<I2C>(&mut self, i2c: &mut I2C) -> Result<bool, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        Ok(self.read_sys_ctrl2(i2c)?.contains(SysCtrl2::DSG_ON))
+    }
+
+    // CHG and DSG from a single SYS_CTRL2 read
+    pub fn fet_state<I2C>(&mut self, i2c: &mut I2C) -> Result<FetState, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        let sys_ctrl2 = self.read_sys_ctrl2(i2c)?;
+        Ok(FetState {
+            charge: sys_ctrl2.contains(SysCtrl2::CHG_ON),
+            discharge: sys_ctrl2.contains(SysCtrl2::DSG_ON)
+        })
+    }
+
+    pub fn read_sys_ctrl1<I2C>(&mut self, i2c: &mut I2C) -> Result<SysCtrl1, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        Ok(SysCtrl1::from_bits_truncate(self.read_u8(i2c, regs::SYS_CTRL1)?))
+    }
+
+    pub fn read_sys_ctrl2<I2C>(&mut self, i2c: &mut I2C) -> Result<SysCtrl2, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        Ok(SysCtrl2::from_bits_truncate(self.read_u8(i2c, regs::SYS_CTRL2)?))
+    }
+
+    // Single read-modify-write of SYS_CTRL1, returns the value written
+    pub fn modify_sys_ctrl1<I2C, F>(&mut self, i2c: &mut I2C, f: F) -> Result<SysCtrl1, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead,
+              F: FnOnce(&mut SysCtrl1)
+    {
+        let mut flags = self.read_sys_ctrl1(i2c)?;
+        f(&mut flags);
+        self.write_raw(i2c, regs::SYS_CTRL1, &[flags.bits()])?;
+        self.temp_source = Some(if flags.contains(SysCtrl1::TEMP_SEL) {
+            TemperatureSource::ExternalThermistor
+        } else {
+            TemperatureSource::InternalDie
+        });
+        Ok(flags)
+    }
+
+    // Single read-modify-write of SYS_CTRL2, returns the value written
+    pub fn modify_sys_ctrl2<I2C, F>(&mut self, i2c: &mut I2C, f: F) -> Result<SysCtrl2, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead,
+              F: FnOnce(&mut SysCtrl2)
+    {
+        let mut flags = self.read_sys_ctrl2(i2c)?;
+        f(&mut flags);
+        self.write_raw(i2c, regs::SYS_CTRL2, &[flags.bits()])?;
+        Ok(flags)
+    }
+
+    // Returns the device to a known state: faults cleared, CHG/DSG off, CC_CFG recommended value.
+    // init() must be called again afterwards.
+    pub fn reset<I2C>(&mut self, i2c: &mut I2C) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        self.init_complete = false;
+        self.cells_read = false;
+        self.temp_source = None;
+        self.modify_sys_ctrl2(i2c, |flags| flags.remove(SysCtrl2::CHG_ON | SysCtrl2::DSG_ON))?;
+        self.sys_stat_reset(i2c, SysStat::ALL)?;
+        self.write_raw(i2c, regs::CC_CFG, &[CC_CFG_RECOMMENDED])
+    }
+
+    // SHUT_A/SHUT_B write sequence, `between` runs between the writes. The last write clears ADC_EN and TEMP_SEL
+    // even if the device stays awake, so init() is needed afterwards in any case.
+    fn ship_sequence<I2C, F>(&mut self, i2c: &mut I2C, mut between: F) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead,
+              F: FnMut()
+    {
+        self.init_complete = false;
+        self.cells_read = false;
+        self.write_raw(i2c, regs::SYS_CTRL1, &[SysCtrl1::empty().bits()])?;
+        between();
+        self.write_raw(i2c, regs::SYS_CTRL1, &[SysCtrl1::SHUT_B.bits()])?;
+        between();
+        self.write_raw(i2c, regs::SYS_CTRL1, &[SysCtrl1::SHUT_A.bits()])
+    }
+
+    pub fn ship_enter<I2C>(&mut self, i2c: &mut I2C) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        self.ship_sequence(i2c, || {})
+    }
+
+    // ship_enter() with SHIP_WRITE_DELAY_MS between the writes, then checks that the device stopped answering
+    // after SHIP_SHUTDOWN_MS. ShipModeFailed if SYS_CTRL1 can still be read. init() is needed after a wake.
+    pub fn ship_enter_verified<I2C, D>(&mut self, i2c: &mut I2C, delay: &mut D) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead,
+              D: embedded_hal::blocking::delay::DelayMs<u16>
+    {
+        self.ship_sequence(i2c, || delay.delay_ms(SHIP_WRITE_DELAY_MS))?;
+        delay.delay_ms(SHIP_SHUTDOWN_MS);
+        if self.read_sys_ctrl1(i2c).is_ok() {
+            return Err(Error::ShipModeFailed);
+        }
+        Ok(())
+    }
+
+    // REGOUT is up if the device answers on the bus (not in SHIP mode), a NACK means it is off and any other
+    // error is passed through. There is no enable bit in SYS_CTRL1/SYS_CTRL2: the LDO runs whenever the device is
+    // out of SHIP mode. The only way to turn it off is ship_enter, which also powers down everything fed from
+    // REGOUT, including a host MCU, until a boot signal on TS1.
+    pub fn is_regout_enabled<I2C>(&mut self, i2c: &mut I2C) -> Result<bool, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        match self.read_sys_ctrl1(i2c) {
+            Ok(_) => Ok(true),
+            Err(Error::I2CError) => Ok(false),
+            Err(e) => Err(e)
+        }
+    }
+
+    // Re-establishes communication after the device left SHIP mode, e.g. woken by charger insertion.
+    // SHIP mode can only be exited by a boot signal on TS1 (pulled above V_BOOT), there is no I2C way out,
+    // see wake_with_boot_pin if the MCU drives TS1 itself. Waits WAKE_BOOT_MS for the device to boot and
+    // checks communication. Registers are back to defaults afterwards, so init() has to be run again.
+    pub fn wake<I2C, D>(&mut self, i2c: &mut I2C, delay: &mut D) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead,
+              D: embedded_hal::blocking::delay::DelayMs<u16>
+    {
+        delay.delay_ms(WAKE_BOOT_MS);
+        self.init_complete = false;
+        self.cells_read = false;
+        self.temp_source = None;
+        Self::check_communication(i2c, self.dev_address, self.use_crc)
+    }
+
+    // Pulses the BOOT (TS1) line through a MCU pin for BOOT_PULSE_MS and then does the same as wake.
+    // The pin must be connected to TS1 through the boot circuit from the reference design.
+    pub fn wake_with_boot_pin<I2C, P, D>(&mut self, i2c: &mut I2C, boot_pin: &mut P, delay: &mut D) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead,
+              P: embedded_hal::digital::v2::OutputPin,
+              D: embedded_hal::blocking::delay::DelayMs<u16>
+    {
+        boot_pin.set_high().map_err(|_| Error::PinError)?;
+        delay.delay_ms(BOOT_PULSE_MS);
+        boot_pin.set_low().map_err(|_| Error::PinError)?;
+        self.wake(i2c, delay)
+    }
+
+    // reading * gain + offset, same conversion as used for cell voltages
+    pub fn convert_adc(&self, reading: u16) -> MilliVolts {
+        self.adc_transfer_function().apply(reading)
+    }
+
+    fn adc_transfer_function(&self) -> AdcTransferFunction {
+        AdcTransferFunction {
+            gain: self.adc_gain,
+            offset: self.adc_offset
+        }
+    }
+
+    // Lowest and highest programmable OV threshold, depends on ADC gain and offset so only known after read_adc_characteristics
+    pub fn ov_range(&self) -> Result<(MilliVolts, MilliVolts), Error> {
+        if self.adc_gain == 0 {
+            return Err(Error::Uninitialized);
+        }
+        Ok(self.ov_voltage_range())
+    }
+
+    pub fn uv_range(&self) -> Result<(MilliVolts, MilliVolts), Error> {
+        if self.adc_gain == 0 {
+            return Err(Error::Uninitialized);
+        }
+        Ok(self.uv_voltage_range())
+    }
+
+    fn ov_voltage_range(&self) -> (MilliVolts, MilliVolts) {
+        let min_adc_reading = 0b10_0000_0000_1000;
+        let max_adc_reading = 0b10_1111_1111_1000;
+        (self.adc_transfer_function().apply(min_adc_reading), self.adc_transfer_function().apply(max_adc_reading))
+    }
+
+    fn uv_voltage_range(&self) -> (MilliVolts, MilliVolts) {
+        let min_adc_reading = 0b01_0000_0000_0000;
+        let max_adc_reading = 0b01_1111_1111_0000;
+        (self.adc_transfer_function().apply(min_adc_reading), self.adc_transfer_function().apply(max_adc_reading))
+    }
+
+    /// Runs all init stages in order: `read_adc_characteristics`, `program_protection`, `enable_coulomb_counter`.
+    /// Stages can also be called one by one (e.g. from a cooperative scheduler) and retried individually,
+    /// but must keep this order: OV/UV thresholds depend on the ADC gain and offset.
+    pub fn init<I2C>(&mut self, i2c: &mut I2C, config: &Config) -> Result<CalculatedValues, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        self.read_adc_characteristics(i2c)?;
+        let values = self.program_protection(i2c, config)?;
+        self.enable_coulomb_counter(i2c)?;
+        Ok(values)
+    }
+
+    /// Init stage 2: writes PROTECT1..3, OV_TRIP, UV_TRIP and CC_CFG.
+    /// Returns `Error::Uninitialized` if `read_adc_characteristics` wasn't called yet.
+    pub fn program_protection<I2C>(&mut self, i2c: &mut I2C, config: &Config) -> Result<CalculatedValues, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        let (block, values) = self.protection_block(config)?;
+        #[cfg(feature = "defmt")]
+        if config.cc_cfg != CC_CFG_RECOMMENDED {
+            defmt::warn!("CC_CFG={:#04x} differs from the recommended {:#04x}", config.cc_cfg, CC_CFG_RECOMMENDED);
+        }
+        self.write_raw(i2c, regs::PROTECT1, &block)?;
+        self.shunt = config.shunt;
+        self.init_complete = true;
+        Ok(values)
+    }
+
+    /// Values `init` would program for this config, without touching the bus.
+    /// Needs ADC gain and offset from `read_adc_characteristics` or `import_calibration`.
+    pub fn compute_config(&self, config: &Config) -> Result<CalculatedValues, Error> {
+        self.protection_block(config).map(|(_, values)| values)
+    }
+
+    // OV_TRIP/UV_TRIP hold ADC bits 11:4, bits 13:12 are fixed (0b10 for OV, 0b01 for UV) as are bits 3:0
+    // (0b1000 for OV, 0 for UV). Never programs a looser limit than asked for: `round_up` false picks the highest
+    // step that converts back to at most the threshold (OV), true the lowest one at or above it (UV).
+    // None if it falls outside the 8-bit field.
+    fn trip_bits(&self, threshold: MilliVolts, prefix: i64, low_bits: i64, round_up: bool) -> Option<u8> {
+        let gain = self.adc_gain as i64;
+        let step = 16 * gain;
+        let offset = self.adc_offset as i64 * 1000 + low_bits * gain;
+        // AdcTransferFunction truncates to mV, so any step below (threshold + 1mV) converts back to <= threshold
+        let trip = if round_up {
+            (threshold.0 as i64 * 1000 - offset + step - 1).div_euclid(step)
+        } else {
+            ((threshold.0 as i64 + 1) * 1000 - 1 - offset).div_euclid(step)
+        };
+        if trip >> 8 != prefix {
+            return None;
+        }
+        Some((trip & 0xff) as u8)
+    }
+
+    // OV_TRIP contents for the threshold and the threshold they result in
+    fn ov_trip(&self, threshold: MilliVolts) -> Result<(u8, MilliVolts), Error> {
+        let ov_limits = self.ov_voltage_range();
+        if !(threshold >= ov_limits.0 && threshold <= ov_limits.1) {
+            return Err(Error::OVThresholdUnobtainable(ov_limits.0, ov_limits.1));
+        }
+        let ov_bits = self.trip_bits(threshold, 0b10, 0b1000, false)
+            .ok_or(Error::OVThresholdUnobtainable(ov_limits.0, ov_limits.1))?;
+        Ok((ov_bits, self.adc_transfer_function().apply(0b10_0000_0000_1000 | ((ov_bits as u16) << 4))))
+    }
+
+    // UV_TRIP contents for the threshold and the threshold they result in
+    fn uv_trip(&self, threshold: MilliVolts) -> Result<(u8, MilliVolts), Error> {
+        let uv_limits = self.uv_voltage_range();
+        if !(threshold >= uv_limits.0 && threshold <= uv_limits.1) {
+            return Err(Error::UVThresholdUnobtainable(uv_limits.0, uv_limits.1));
+        }
+        let uv_bits = self.trip_bits(threshold, 0b01, 0, true)
+            .ok_or(Error::UVThresholdUnobtainable(uv_limits.0, uv_limits.1))?;
+        Ok((uv_bits, self.adc_transfer_function().apply(0b01_0000_0000_0000 | ((uv_bits as u16) << 4))))
+    }
+
+    // PROTECT1..CC_CFG register contents and the thresholds they result in
+    fn protection_block(&self, config: &Config) -> Result<([u8; 6], CalculatedValues), Error> {
+        if self.adc_gain == 0 {
+            return Err(Error::Uninitialized);
+        }
+
+        let scd_threshold = SCDThreshold::from_current(config.scd_threshold, config.shunt);
+        let ocd_threshold = OCDThreshold::from_current(config.ocd_threshold, config.shunt);
+        let range_to_use = config.check_ocdscd_range()?;
+        let scd_bits = scd_threshold.bits(range_to_use);
+        let ocd_bits = ocd_threshold.bits(range_to_use);
+
+        let mut block = [0u8; 6]; // PROTECT1..CC_CFG
+        block[0] = range_to_use.bits() | config.scd_delay.bits() | scd_bits; // PROTECT1
+        block[1] = config.ocd_delay.bits() | ocd_bits; // PROTECT2
+        block[2] = config.uv_delay.bits() | config.ov_delay.bits(); // PROTECT3
+
+        let (ov_bits, ov_threshold) = self.ov_trip(config.ov_threshold)?;
+        let (uv_bits, uv_threshold) = self.uv_trip(config.uv_threshold)?;
+
+        block[3] = ov_bits; // OV_TRIP
+        block[4] = uv_bits; // UV_TRIP
+        block[5] = config.cc_cfg; // CC_CFG
+
+        Ok((block, CalculatedValues{
+            ocdscd_range_used: range_to_use,
+            scd_threshold: Amperes(((scd_threshold as u32) * 1000) / config.shunt.0),
+            ocd_threshold: Amperes(((ocd_threshold as u32) * 1000) / config.shunt.0),
+            uv_threshold,
+            ov_threshold,
+            uv_recovery: uv_threshold + config.uv_hysteresis,
+            ov_recovery: ov_threshold - config.ov_hysteresis,
+            shunt: config.shunt,
+            scd_delay: config.scd_delay,
+            ocd_delay: config.ocd_delay,
+            uv_delay: config.uv_delay,
+            ov_delay: config.ov_delay,
+            cc_cfg: config.cc_cfg,
+        }))
+    }
+
+    // Single threshold updates after init(), each returns the value actually programmed.
+    // OV/UV only rewrite OV_TRIP/UV_TRIP, delays in PROTECT3 are kept.
+    pub fn set_ov_threshold<I2C>(&mut self, i2c: &mut I2C, threshold: MilliVolts) -> Result<MilliVolts, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        if !self.is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+        let (bits, actual) = self.ov_trip(threshold)?;
+        self.write_raw(i2c, regs::OV_TRIP, &[bits])?;
+        Ok(actual)
+    }
+
+    pub fn set_uv_threshold<I2C>(&mut self, i2c: &mut I2C, threshold: MilliVolts) -> Result<MilliVolts, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        if !self.is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+        let (bits, actual) = self.uv_trip(threshold)?;
+        self.write_raw(i2c, regs::UV_TRIP, &[bits])?;
+        Ok(actual)
+    }
+
+    // SCD and OCD share RSNS: the other threshold is read back and kept, RSNS is switched if the new threshold
+    // needs it. OCDSCDRangeMismatch if the other threshold does not fit the new range, nothing is written then.
+    pub fn set_scd<I2C>(&mut self, i2c: &mut I2C, threshold: Amperes, delay: SCDDelay) -> Result<Amperes, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        let (mut protect, _, ocd_threshold) = self.read_ocdscd(i2c)?;
+        let scd_threshold = SCDThreshold::from_current_checked(threshold, self.shunt)?;
+        let range = OCDSCDRange::resolve(scd_threshold, ocd_threshold, self.shunt)?;
+        protect[0] = range.bits() | delay.bits() | scd_threshold.bits(range);
+        protect[1] = (protect[1] & 0x70) | ocd_threshold.bits(range); // keep OCD_D
+        self.write_raw(i2c, regs::PROTECT1, &protect)?;
+        Ok(Amperes(((scd_threshold as u32) * 1000) / self.shunt.0))
+    }
+
+    pub fn set_ocd<I2C>(&mut self, i2c: &mut I2C, threshold: Amperes, delay: OCDDelay) -> Result<Amperes, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        let (mut protect, scd_threshold, _) = self.read_ocdscd(i2c)?;
+        let ocd_threshold = OCDThreshold::from_current_checked(threshold, self.shunt)?;
+        let range = OCDSCDRange::resolve(scd_threshold, ocd_threshold, self.shunt)?;
+        protect[0] = range.bits() | (protect[0] & 0x18) | scd_threshold.bits(range); // keep SCD_D
+        protect[1] = delay.bits() | ocd_threshold.bits(range);
+        self.write_raw(i2c, regs::PROTECT1, &protect)?;
+        Ok(Amperes(((ocd_threshold as u32) * 1000) / self.shunt.0))
+    }
+
+    // PROTECT1..PROTECT2 and the SCD and OCD steps currently programmed
+    fn read_ocdscd<I2C>(&mut self, i2c: &mut I2C) -> Result<([u8; 2], SCDThreshold, OCDThreshold), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        if !self.is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+        self.check_shunt()?;
+        let mut protect = [0u8; 2];
+        self.read_raw(i2c, regs::PROTECT1, &mut protect)?;
+        let range = if protect[0] & OCDSCDRange::Upper.bits() != 0 { OCDSCDRange::Upper } else { OCDSCDRange::Lower };
+        Ok((protect, SCDThreshold::from_bits(protect[0], range), OCDThreshold::from_bits(protect[1], range)))
+    }
+
+    /// Init stage 3: enables continuous coulomb counter operation (CC_EN).
+    pub fn enable_coulomb_counter<I2C>(&mut self, i2c: &mut I2C) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        self.modify_sys_ctrl2(i2c, |flags| flags.insert(SysCtrl2::CC_EN))?;
+        Ok(())
+    }
+
+    pub fn enable_adc<I2C>(&mut self, i2c: &mut I2C, enable: bool) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        self.modify_sys_ctrl1(i2c, |flags| flags.set(SysCtrl1::ADC_EN, enable))?;
+        Ok(())
+    }
+
+    pub fn is_adc_enabled<I2C>(&mut self, i2c: &mut I2C) -> Result<bool, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        Ok(self.read_sys_ctrl1(i2c)?.contains(SysCtrl1::ADC_EN))
+    }
+
+    pub fn set_temperature_source<I2C>(&mut self, i2c: &mut I2C, source: TemperatureSource) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        let is_external = source == TemperatureSource::ExternalThermistor;
+        self.modify_sys_ctrl1(i2c, |flags| flags.set(SysCtrl1::TEMP_SEL, is_external))?;
+        Ok(())
+    }
+
+    // Source tracked from SYS_CTRL1 writes, None until set_temperature_source()/modify_sys_ctrl1() or a temperature read
+    pub fn known_temperature_source(&self) -> Option<TemperatureSource> {
+        self.temp_source
+    }
+
+    fn cached_temperature_source<I2C>(&mut self, i2c: &mut I2C) -> Result<TemperatureSource, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        match self.temp_source {
+            Some(source) => Ok(source),
+            None => {
+                let source = self.temperature_source(i2c)?;
+                self.temp_source = Some(source);
+                Ok(source)
+            }
+        }
+    }
+
+    pub fn temperature_source<I2C>(&mut self, i2c: &mut I2C) -> Result<TemperatureSource, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        if self.read_sys_ctrl1(i2c)?.contains(SysCtrl1::TEMP_SEL) {
+            Ok(TemperatureSource::ExternalThermistor)
+        } else {
+            Ok(TemperatureSource::InternalDie)
+        }
+    }
+
+    pub fn coulomb_counter_mode<I2C>(&mut self, i2c: &mut I2C, mode: CoulombCounterMode) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        self.modify_sys_ctrl2(i2c, |flags| {
+            flags.set(SysCtrl2::CC_ONESHOT, mode == CoulombCounterMode::OneShot);
+            flags.set(SysCtrl2::CC_EN, mode == CoulombCounterMode::Continuous);
+        })?;
+        Ok(())
+    }
+
+    // Mode from SYS_CTRL2: Continuous if CC_EN is set, OneShot while a one-shot conversion is pending
+    // (CC_ONESHOT clears itself once it is done), Disabled otherwise
+    pub fn read_coulomb_counter_mode<I2C>(&mut self, i2c: &mut I2C) -> Result<CoulombCounterMode, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        let flags = self.read_sys_ctrl2(i2c)?;
+        if flags.contains(SysCtrl2::CC_EN) {
+            Ok(CoulombCounterMode::Continuous)
+        } else if flags.contains(SysCtrl2::CC_ONESHOT) {
+            Ok(CoulombCounterMode::OneShot)
+        } else {
+            Ok(CoulombCounterMode::Disabled)
+        }
+    }
+
+    // True if CC_EN (continuous mode) is set, a pending one-shot is not reported
+    pub fn is_cc_enabled<I2C>(&mut self, i2c: &mut I2C) -> Result<bool, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        Ok(self.read_sys_ctrl2(i2c)?.contains(SysCtrl2::CC_EN))
+    }
+
+    // CHG, DSG and CC mode in one read-modify-write of SYS_CTRL2 instead of three
+    pub fn configure_outputs<I2C>(&mut self, i2c: &mut I2C, chg: bool, dsg: bool, cc: CoulombCounterMode) -> Result<SysCtrl2, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        self.modify_sys_ctrl2(i2c, |sys_ctrl2| {
+            sys_ctrl2.set(SysCtrl2::CHG_ON, chg);
+            sys_ctrl2.set(SysCtrl2::DSG_ON, dsg);
+            sys_ctrl2.set(SysCtrl2::CC_ONESHOT, cc == CoulombCounterMode::OneShot);
+            sys_ctrl2.set(SysCtrl2::CC_EN, cc == CoulombCounterMode::Continuous);
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct Telemetry<const X: usize> {
+    #[serde(with = "cell_array")]
+    pub cells: [MilliVolts; X],
+    pub cell_count: u8,
+    pub voltage: MilliVolts,
+    pub temperature: Temperature,
+    pub current: MilliAmperes,
+}
+
+impl<const X: usize> Telemetry<X> {
+    pub fn cells(&self) -> &[MilliVolts] {
+        &self.cells[..self.cell_count as usize]
+    }
+}
+
+// I2C bus wrapper keeping the bus idle for a fixed time before each transaction, for hosts that would otherwise
+// start the next one before the device is ready. Pass it wherever the driver takes the bus.
+pub struct SettleDelay<I2C, D> {
+    i2c: I2C,
+    delay: D,
+    us: u32
+}
+
+impl<I2C, D> SettleDelay<I2C, D> where D: embedded_hal::blocking::delay::DelayUs<u32> {
+    // DEFAULT_SETTLE_US between transactions
+    pub fn new(i2c: I2C, delay: D) -> Self {
+        Self::with_delay_us(i2c, delay, DEFAULT_SETTLE_US)
+    }
+
+    pub fn with_delay_us(i2c: I2C, delay: D, us: u32) -> Self {
+        SettleDelay { i2c, delay, us }
+    }
+
+    pub fn delay_us(&self) -> u32 {
+        self.us
+    }
+
+    pub fn set_delay_us(&mut self, us: u32) {
+        self.us = us;
+    }
+
+    // Returns the bus and the delay
+    pub fn release(self) -> (I2C, D) {
+        (self.i2c, self.delay)
+    }
+}
+
+impl<I2C, D> embedded_hal::blocking::i2c::Write for SettleDelay<I2C, D>
+    where I2C: embedded_hal::blocking::i2c::Write,
+          D: embedded_hal::blocking::delay::DelayUs<u32>
+{
+    type Error = I2C::Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.delay.delay_us(self.us);
+        self.i2c.write(address, bytes)
+    }
+}
+
+impl<I2C, D> embedded_hal::blocking::i2c::WriteRead for SettleDelay<I2C, D>
+    where I2C: embedded_hal::blocking::i2c::WriteRead,
+          D: embedded_hal::blocking::delay::DelayUs<u32>
+{
+    type Error = I2C::Error;
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.delay.delay_us(self.us);
+        self.i2c.write_read(address, bytes, buffer)
+    }
+}
+
+// Scripted I2C bus for testing code built on top of the driver (enable the `mock` feature).
+// Expected transactions are checked in order, any mismatch panics.
+#[cfg(any(test, feature = "mock"))]
+pub mod mock {
+    extern crate std;
+    use std::vec::Vec;
+    use std::collections::VecDeque;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Transaction {
+        pub address: u8,
+        // register address followed by data
+        pub bytes: Vec<u8>,
+        // None for a write, bytes returned for a write_read
+        pub response: Option<Vec<u8>>,
+        // transaction is checked and then fails as if the device did not acknowledge
+        pub nack: bool,
+    }
+
+    impl Transaction {
+        pub fn write(address: u8, reg: u8, data: &[u8]) -> Self {
+            let mut bytes = Vec::with_capacity(data.len() + 1);
+            bytes.push(reg);
+            bytes.extend_from_slice(data);
+            Transaction { address, bytes, response: None, nack: false }
+        }
+
+        pub fn read(address: u8, reg: u8, response: &[u8]) -> Self {
+            Transaction { address, bytes: std::vec![reg], response: Some(response.to_vec()), nack: false }
+        }
+
+        pub fn with_nack(mut self) -> Self {
+            self.nack = true;
+            self
+        }
+    }
+
+    #[derive(Debug, Default)]
+    pub struct I2cMock {
+        expected: VecDeque<Transaction>,
+    }
+
+    impl I2cMock {
+        pub fn new(expected: &[Transaction]) -> Self {
+            I2cMock { expected: expected.iter().cloned().collect() }
+        }
+
+        pub fn expect(&mut self, transaction: Transaction) {
+            self.expected.push_back(transaction);
+        }
+
+        // Panics if some of the expected transactions were not issued
+        pub fn done(&mut self) {
+            assert!(self.expected.is_empty(), "i2c mock: transactions not issued: {:?}", self.expected);
+        }
+
+        fn next(&mut self, actual: &Transaction) -> Transaction {
+            let expected = match self.expected.pop_front() {
+                Some(t) => t,
+                None => panic!("i2c mock: unexpected transaction {:?}", actual),
+            };
+            assert_eq!(
+                (expected.address, &expected.bytes, expected.response.is_some()),
+                (actual.address, &actual.bytes, actual.response.is_some()),
+                "i2c mock: transaction mismatch"
+            );
+            expected
+        }
+    }
+
+    impl embedded_hal::blocking::i2c::Write for I2cMock {
+        type Error = ();
+
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+            let actual = Transaction { address, bytes: bytes.to_vec(), response: None, nack: false };
+            if self.next(&actual).nack {
+                return Err(());
+            }
+            Ok(())
+        }
+    }
+
+    impl embedded_hal::blocking::i2c::WriteRead for I2cMock {
+        type Error = ();
+
+        fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+            let actual = Transaction { address, bytes: bytes.to_vec(), response: Some(Vec::new()), nack: false };
+            let expected = self.next(&actual);
+            if expected.nack {
+                return Err(());
+            }
+            let response = expected.response.unwrap_or_default();
+            assert_eq!(response.len(), buffer.len(), "i2c mock: read length mismatch for {:?}", expected.bytes);
+            buffer.copy_from_slice(&response);
+            Ok(())
+        }
+    }
+}
+
+// serde only implements arrays up to a fixed length, not [T; X], so cells go through a tuple of X elements
+mod cell_array {
+    use core::fmt;
+    use serde::{Serializer, Deserializer};
+    use serde::ser::SerializeTuple;
+    use serde::de::{self, Visitor, SeqAccess};
+    use crate::MilliVolts;
+
+    pub fn serialize<S: Serializer, const X: usize>(cells: &[MilliVolts; X], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(X)?;
+        for cell in cells {
+            tuple.serialize_element(cell)?;
+        }
+        tuple.end()
+    }
+
+    struct CellArrayVisitor<const X: usize>;
+
+    impl<'de, const X: usize> Visitor<'de> for CellArrayVisitor<X> {
+        type Value = [MilliVolts; X];
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "an array of {} cell voltages", X)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut cells = [MilliVolts(0); X];
+            for (i, cell) in cells.iter_mut().enumerate() {
+                *cell = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(i, &self))?;
+            }
+            Ok(cells)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const X: usize>(deserializer: D) -> Result<[MilliVolts; X], D::Error> {
+        deserializer.deserialize_tuple(X, CellArrayVisitor::<X>)
+    }
+}
+
+// Set of cells, bit n = cell n (CELLBAL1 bit 0 = cell 0, CELLBAL2 bit 0 = cell 5, ...)
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CellMask(pub u16);
+
+impl CellMask {
+    pub fn bits(&self) -> u16 {
+        self.0
+    }
+
+    pub fn contains(&self, cell: usize) -> bool {
+        cell < 16 && self.0 & (1 << cell) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=usize> {
+        let mask = self.0;
+        (0..16).filter(move |cell| mask & (1 << cell) != 0)
+    }
+}
+
+// Passive balancing over time: target cells more than `tolerance` above the lowest cell are balanced,
+// neighbours of the same group take turns on successive steps as they can not balance together.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BalanceSession {
+    targets: CellMask,
+    tolerance: MilliVolts,
+    step: u32,
+    done: bool,
+}
+
+impl BalanceSession {
+    pub fn new(targets: CellMask, tolerance: MilliVolts) -> Self {
+        BalanceSession { targets, tolerance, step: 0, done: false }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    // Legal mask for the next step from (physical cell index, voltage) pairs, empty once all targets are
+    // within tolerance of the minimum cell.
+    pub fn next_mask<I: IntoIterator<Item=(usize, MilliVolts)>>(&mut self, cells: I) -> CellMask {
+        let mut min = None;
+        let mut voltages = [None; 16];
+        for (cell, mv) in cells.into_iter().filter(|(cell, _)| *cell < 16) {
+            voltages[cell] = Some(mv);
+            min = Some(min.map_or(mv, |m: MilliVolts| m.min(mv)));
+        }
+        let min = match min {
+            Some(min) => min,
+            None => return CellMask(0),
+        };
+        let high = self.targets.iter()
+            .filter(|&cell| voltages[cell].is_some_and(|mv| mv > min + self.tolerance))
+            .fold(0u16, |mask, cell| mask | (1 << cell));
+        self.done = high == 0;
+        let mask = Self::rotated_mask(high, self.step);
+        self.step = self.step.wrapping_add(1);
+        CellMask(mask)
+    }
+
+    // Cells on odd and even positions in a group alternate taking precedence, the rest are added where
+    // no neighbour is balancing.
+    fn rotated_mask(requested: u16, step: u32) -> u16 {
+        let preferred = (0..16).filter(|cell| (cell % 5) % 2 == (step % 2) as usize);
+        let others = (0..16).filter(|cell| (cell % 5) % 2 != (step % 2) as usize);
+        let mut mask = 0u16;
+        for cell in preferred.chain(others) {
+            if requested & (1 << cell) == 0 {
+                continue;
+            }
+            let left = cell % 5 != 0 && mask & (1 << (cell - 1)) != 0;
+            let right = cell % 5 != 4 && mask & (1 << (cell + 1)) != 0;
+            if !left && !right {
+                mask |= 1 << cell;
+            }
+        }
+        mask
+    }
+
+    // Computes the next mask from the last cell_voltages() reading and applies it with enable_balancing()
+    pub fn step<I2C, const X: usize>(&mut self, bq769x0: &mut BQ769x0<X>, i2c: &mut I2C) -> Result<CellMask, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        if bq769x0.cells_iter().next().is_none() {
+            return Err(Error::Uninitialized);
+        }
+        let mask = self.next_mask(bq769x0.cells_iter());
+        bq769x0.enable_balancing(i2c, mask.bits())?;
+        Ok(mask)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ProtectionState {
+    Normal,
+    // FETs off, latched faults are kept for the cooldown
+    Tripped(SysStat),
+    // Faults cleared, FETs are turned back on if none come back by the next poll and no cell is below the
+    // discharge floor, otherwise it stays here
+    Recovering,
+}
+
+// Fault handling around SYS_STAT: a fault turns CHG and DSG off, after `cooldown_polls` polls the latched flags
+// are cleared and the FETs re-enabled once a poll sees no fault. poll() is expected to be called periodically.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ProtectionManager {
+    state: ProtectionState,
+    cooldown_polls: u32,
+    remaining: u32,
+}
+
+impl ProtectionManager {
+    pub fn new(cooldown_polls: u32) -> Self {
+        ProtectionManager { state: ProtectionState::Normal, cooldown_polls, remaining: 0 }
+    }
+
+    pub fn state(&self) -> ProtectionState {
+        self.state
+    }
+
+    pub fn poll<I2C, const X: usize>(&mut self, bq769x0: &mut BQ769x0<X>, i2c: &mut I2C) -> Result<ProtectionState, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        if !bq769x0.is_initialized() {
+            return Err(Error::Uninitialized);
+        }
+        let faults = bq769x0.faults(i2c)?;
+        self.state = match self.state {
+            ProtectionState::Normal | ProtectionState::Recovering if !faults.is_empty() => {
+                // the chip only drops the FETs by itself on SCD/OCD/UV/OV
+                bq769x0.modify_sys_ctrl2(i2c, |flags| flags.remove(SysCtrl2::CHG_ON | SysCtrl2::DSG_ON))?;
+                self.remaining = self.cooldown_polls;
+                ProtectionState::Tripped(faults)
+            }
+            ProtectionState::Normal => ProtectionState::Normal,
+            ProtectionState::Recovering => match bq769x0.check_discharge_floor() {
+                Ok(()) => {
+                    bq769x0.modify_sys_ctrl2(i2c, |flags| flags.insert(SysCtrl2::CHG_ON | SysCtrl2::DSG_ON))?;
+                    ProtectionState::Normal
+                }
+                Err(Error::CellBelowFloor(_, _)) => ProtectionState::Recovering,
+                Err(e) => return Err(e)
+            },
+            ProtectionState::Tripped(tripped) => {
+                let tripped = tripped | faults;
+                if self.remaining > 0 {
+                    self.remaining -= 1;
+                }
+                if self.remaining == 0 {
+                    bq769x0.sys_stat_reset(i2c, tripped & SysStat::ALL)?;
+                    ProtectionState::Recovering
+                } else {
+                    ProtectionState::Tripped(tripped)
+                }
+            }
+        };
+        Ok(self.state)
+    }
+}
+
+// Any of the three chips behind one type, for when the variant is only known at runtime
+pub enum DynBq769x0 {
+    Bq76920(Bq76920),
+    Bq76930(Bq76930),
+    Bq76940(Bq76940),
+}
+
+// Runs the same expression on whichever driver is inside
+macro_rules! dispatch {
+    ($self:ident, $bq:ident => $e:expr) => {
+        match $self {
+            DynBq769x0::Bq76920($bq) => $e,
+            DynBq769x0::Bq76930($bq) => $e,
+            DynBq769x0::Bq76940($bq) => $e,
+        }
+    };
+}
+
+impl DynBq769x0 {
+    // variant is BQ76920, BQ76930 or BQ76940, InvalidVariant otherwise
+    pub fn new(variant: usize, dev_address: u8, cell_count: u8, use_crc: bool) -> Result<Self, Error> {
+        match variant {
+            BQ76920 => Bq76920::try_new(dev_address, cell_count, use_crc).map(DynBq769x0::Bq76920),
+            BQ76930 => Bq76930::try_new(dev_address, cell_count, use_crc).map(DynBq769x0::Bq76930),
+            BQ76940 => Bq76940::try_new(dev_address, cell_count, use_crc).map(DynBq769x0::Bq76940),
+            _ => Err(Error::InvalidVariant(variant)),
+        }
+    }
+
+    // Number of VC inputs of the chip, X of the wrapped BQ769x0<X>
+    pub fn variant(&self) -> usize {
+        match self {
+            DynBq769x0::Bq76920(_) => BQ76920,
+            DynBq769x0::Bq76930(_) => BQ76930,
+            DynBq769x0::Bq76940(_) => BQ76940,
+        }
+    }
+
+    pub fn cell_count(&self) -> u8 {
+        dispatch!(self, bq => bq.cell_count())
+    }
+
+    pub fn i2c_address(&self) -> u8 {
+        dispatch!(self, bq => bq.i2c_address())
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        dispatch!(self, bq => bq.is_initialized())
+    }
+
+    pub fn init<I2C>(&mut self, i2c: &mut I2C, config: &Config) -> Result<CalculatedValues, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        dispatch!(self, bq => bq.init(i2c, config))
+    }
+
+    pub fn cell_voltages<I2C>(&mut self, i2c: &mut I2C) -> Result<&[MilliVolts], Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        dispatch!(self, bq => bq.cell_voltages(i2c))
+    }
+
+    pub fn read_cells_into<I2C>(&mut self, i2c: &mut I2C, out: &mut [MilliVolts]) -> Result<usize, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        dispatch!(self, bq => bq.read_cells_into(i2c, out))
+    }
+
+    pub fn voltage<I2C>(&mut self, i2c: &mut I2C) -> Result<MilliVolts, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        dispatch!(self, bq => bq.voltage(i2c))
+    }
+
+    pub fn current<I2C>(&mut self, i2c: &mut I2C) -> Result<MilliAmperes, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        dispatch!(self, bq => bq.current(i2c))
+    }
+
+    pub fn temperature_all<I2C>(&mut self, i2c: &mut I2C) -> Result<Temperatures, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        dispatch!(self, bq => bq.temperature_all(i2c))
+    }
+
+    pub fn sys_stat<I2C>(&mut self, i2c: &mut I2C) -> Result<SysStat, Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        dispatch!(self, bq => bq.sys_stat(i2c))
+    }
+
+    pub fn sys_stat_reset<I2C>(&mut self, i2c: &mut I2C, flags: SysStat) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        dispatch!(self, bq => bq.sys_stat_reset(i2c, flags))
+    }
+
+    pub fn charge<I2C>(&mut self, i2c: &mut I2C, enable: bool) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        dispatch!(self, bq => bq.charge(i2c, enable))
+    }
+
+    pub fn discharge<I2C>(&mut self, i2c: &mut I2C, enable: bool) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        dispatch!(self, bq => bq.discharge(i2c, enable))
+    }
+
+    pub fn enable_balancing<I2C>(&mut self, i2c: &mut I2C, cells: u16) -> Result<(), Error>
+        where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+    {
+        dispatch!(self, bq => bq.enable_balancing(i2c, cells))
+    }
+}
+
+// Device found by probe()
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ProbeInfo {
+    pub address: u8,
+    pub use_crc: bool,
+}
+
+// Checks whether a BQ769x0 answers at address, trying without CRC first. Writes UV_TRIP, so probe before init().
+pub fn probe<I2C>(i2c: &mut I2C, address: u8) -> Option<ProbeInfo>
+    where I2C: embedded_hal::blocking::i2c::Write + embedded_hal::blocking::i2c::WriteRead
+{
+    // register access does not depend on the variant
+    for &use_crc in [false, true].iter() {
+        if BQ769x0::<BQ76920>::check_communication(i2c, address, use_crc).is_ok() {
+            return Some(ProbeInfo { address, use_crc });
+        }
+    }
+    None
+}
+
+// Linear interpolation in an open-circuit voltage table sorted by ascending voltage, (voltage, SOC in %).
+// Voltages outside the table give the first or last SOC, an empty table gives 0.
+pub fn ocv_to_soc(table: &[(MilliVolts, u8)], ocv: MilliVolts) -> u8 {
+    let (first, last) = match (table.first(), table.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return 0,
+    };
+    if ocv <= first.0 {
+        return first.1;
+    }
+    if ocv >= last.0 {
+        return last.1;
+    }
+    for pair in table.windows(2) {
+        let ((mv0, soc0), (mv1, soc1)) = (pair[0], pair[1]);
+        if ocv <= mv1 {
+            if mv1 == mv0 {
+                return soc1;
+            }
+            let soc = soc0 as i32 + (soc1 as i32 - soc0 as i32) * (ocv.0 - mv0.0) as i32 / (mv1.0 - mv0.0) as i32;
+            return soc as u8;
+        }
+    }
+    last.1
+}
+
+// Pretty-printer for dump_registers() output: decoded control registers, raw ADC readings
+pub struct RegisterDump<'a>(pub &'a [u8; REGISTER_DUMP_LEN]);
+
+impl<'a> fmt::Display for RegisterDump<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let r = |reg: u8| self.0[reg as usize];
+        let pair = |hi: u8| u16::from_be_bytes([r(hi), r(hi + 1)]);
+        writeln!(f, "SYS_STAT  {:#04x} {:?}", r(regs::SYS_STAT), SysStat::from_bits_truncate(r(regs::SYS_STAT)))?;
+        writeln!(f, "CELLBAL   {:#04x} {:#04x} {:#04x}", r(regs::CELLBAL1), r(regs::CELLBAL2), r(regs::CELLBAL3))?;
+        writeln!(f, "SYS_CTRL1 {:#04x} {:?}", r(regs::SYS_CTRL1), SysCtrl1::from_bits_truncate(r(regs::SYS_CTRL1)))?;
+        writeln!(f, "SYS_CTRL2 {:#04x} {:?}", r(regs::SYS_CTRL2), SysCtrl2::from_bits_truncate(r(regs::SYS_CTRL2)))?;
+        writeln!(f, "PROTECT   {:#04x} {:#04x} {:#04x}", r(regs::PROTECT1), r(regs::PROTECT2), r(regs::PROTECT3))?;
+        writeln!(f, "OV_TRIP   {:#04x} UV_TRIP {:#04x} CC_CFG {:#04x}", r(regs::OV_TRIP), r(regs::UV_TRIP), r(regs::CC_CFG))?;
+        for cell in 0..15 {
+            writeln!(f, "VC{:<2}      {}", cell + 1, pair(regs::VC1_HI + cell * 2))?;
+        }
+        writeln!(f, "BAT       {}", pair(regs::BAT_HI))?;
+        writeln!(f, "TS        {} {} {}", pair(regs::TS1_HI), pair(regs::TS2_HI), pair(regs::TS3_HI))?;
+        write!(f, "CC        {}", pair(regs::CC_HI) as i16)
+    }
+}
+
+#[derive(Copy, Clone)]
+struct AdcTransferFunction {
+    gain: u16,
+    offset: i8
+}
+impl AdcTransferFunction {
+    fn apply(&self, adc_reading: u16) -> MilliVolts {
+        let adc_reading = adc_reading as i32;
+        let uv = adc_reading * self.gain as i32 + self.offset as i32 * 1000;
+        MilliVolts((uv / 1000) as u32)
+    }
+}
+
+/// Physical arrangement of the cells on the VC inputs, see `connected_cell_map()`.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CellWiring {
+    /// Cells spread evenly over the groups, lower groups take the extra ones
+    Spread,
+    /// Bit n set if VC(n+1) measures a cell
+    Custom(CellMask)
+}
+
+/// Sign convention of `current()`. With the reference wiring (SRP towards the cells, SRN towards PACK-)
+/// the coulomb counter reads positive while charging, which is the default `ChargePositive`.
+/// Use `DischargePositive` if the shunt is wired the other way or firmware treats discharge as positive.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CurrentPolarity {
+    ChargePositive,
+    DischargePositive
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CoulombCounterMode {
+    Disabled,
+    OneShot,
+    Continuous
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TemperatureSource {
+    InternalDie,
+    ExternalThermistor
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Temperature {
+    InternalDie(DegreesCentigrade),
+    ExternalThermistor(DegreesCentigrade)
+}
+
+// TS readings of the chip, Self::TS_INPUTS of them (1 to 3), derefs to a slice. The storage is a fixed
+// [_; 3] sized for BQ76940 on purpose: [Temperature; X / 5] needs generic_const_exprs, which is nightly only,
+// and the crate builds on stable (like the CRC buffers sized by MAX_READ_LEN).
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Temperatures {
+    readings: [Temperature; 3],
+    len: usize
+}
+
+impl core::ops::Deref for Temperatures {
+    type Target = [Temperature];
+
+    fn deref(&self) -> &[Temperature] {
+        &self.readings[..self.len]
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FetState {
+    pub charge: bool,
+    pub discharge: bool
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AdcCharacteristics {
+    pub gain_uv_per_lsb: u16,
+    pub offset_mv: i8
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Calibration {
+    pub adc_gain: u16, // uV / LSB
+    pub adc_offset: i8, // mV
+    pub shunt: MicroOhms,
+    pub current_offset: MilliAmperes
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OvertemperatureLimits {
+    pub charge: DegreesCentigrade,
+    pub discharge: DegreesCentigrade
+}
+
+// Result of an overtemperature check, charge/discharge is true if the corresponding FET was turned off
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OvertemperatureStatus {
+    pub charge: bool,
+    pub discharge: bool,
+    pub hottest: DegreesCentigrade
+}
+
+impl OvertemperatureStatus {
+    pub fn is_ok(&self) -> bool {
+        !self.charge && !self.discharge
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    struct DummyI2C {
+        pub regs: [u8; 255],
+        // (register, bytes) latched into regs when the front register is read, one entry per read
+        pub scripted_reads: std::collections::VecDeque<(usize, std::vec::Vec<u8>)>,
+        // device does not acknowledge, all transactions fail
+        pub nack: bool,
+        // writes to these registers are ignored (not present on the emulated chip)
+        pub reserved_regs: std::vec::Vec<usize>,
+        // number of write and write_read transactions issued
+        pub writes: usize,
+        pub reads: usize,
+    }
+
+    impl DummyI2C {
+        pub fn new() -> Self {
+            let mut regs = [0u8; 255];
+            regs[0x50] = 0x15;
+            regs[0x51] = 0x2b;
+            regs[0x59] = 0xa3;
+            DummyI2C { regs, scripted_reads: std::collections::VecDeque::new(), nack: false, reserved_regs: std::vec::Vec::new(), writes: 0, reads: 0 }
+        }
+    }
+
+    impl embedded_hal::blocking::i2c::Write for DummyI2C {
+        type Error = ();
+
+        fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+            std::println!("-----------");
+            std::println!("write: {:#04x}", addr);
+            self.writes += 1;
+            if self.nack {
+                return Err(());
+            }
+            let base_reg_addr = bytes[0] as usize;
+            for (i, b) in bytes.iter().skip(1).enumerate() {
+                let reg_addr = base_reg_addr + i;
+                if reg_addr == 0x00 {
+                    self.regs[reg_addr] &= !*b; // SYS_STAT is write 1 to clear
+                } else if self.reserved_regs.contains(&reg_addr) {
+                    continue;
+                } else {
+                    self.regs[reg_addr] = *b;
+                }
+                std::println!("{}/{:#04x}\t<= {:#04x}={:#010b}", reg_addr, reg_addr, *b, *b);
+            }
+
+            Ok(())
+        }
+    }
+
+    impl embedded_hal::blocking::i2c::WriteRead for DummyI2C {
+        type Error = ();
+
+        fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+            std::println!("----------------");
+            std::println!("write_read: {:#04x}", address);
+            self.reads += 1;
+            if self.nack {
+                return Err(());
+            }
+            let base_reg_addr = bytes[0] as usize;
+            if self.scripted_reads.front().map(|(reg, _)| *reg) == Some(base_reg_addr) {
+                let (_, bytes) = self.scripted_reads.pop_front().unwrap();
+                self.regs[base_reg_addr..base_reg_addr + bytes.len()].copy_from_slice(&bytes);
+            }
+            for (i, b) in buffer.iter_mut().enumerate() {
+                let reg_addr = base_reg_addr + i;
+                let reg_value = self.regs[reg_addr];
+                *b = reg_value;
+                std::println!("{}/{:#04x}\t== {:#04x}={:#010b}", reg_addr, reg_addr, reg_value, reg_value);
+            }
+
+            Ok(())
+        }
+    }
+
+    struct DummyDelay {
+        pub total_ms: u32,
+    }
+
+    impl embedded_hal::blocking::delay::DelayMs<u16> for DummyDelay {
+        fn delay_ms(&mut self, ms: u16) {
+            self.total_ms += ms as u32;
+        }
+    }
+
+    impl embedded_hal::blocking::delay::DelayUs<u32> for DummyDelay {
+        fn delay_us(&mut self, us: u32) {
+            self.total_ms += us / 1000;
+        }
+    }
+
+    struct DummyPin {
+        pub history: std::vec::Vec<bool>,
+    }
+
+    impl embedded_hal::digital::v2::OutputPin for DummyPin {
+        type Error = ();
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.history.push(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.history.push(true);
+            Ok(())
+        }
+    }
+
+    // CRC framed register contents as they go over the bus: byte,crc,byte,crc,... The first CRC also covers
+    // `prefix`: the address byte for reads ((address << 1) | 1), address byte and register for writes.
+    fn crc_block(prefix: &[u8], data: &[u8]) -> std::vec::Vec<u8> {
+        let mut block = std::vec::Vec::new();
+        for (i, b) in data.iter().enumerate() {
+            let mut crc = crc_any::CRCu8::crc8();
+            if i == 0 {
+                crc.digest(prefix);
+            }
+            crc.digest(&[*b]);
+            block.push(*b);
+            block.push(crc.get_crc());
+        }
+        block
+    }
+
+    fn test_config() -> crate::Config {
+        use crate::*;
+
+        Config {
+            shunt: MicroOhms(667),
+            scd_delay: SCDDelay::_400uS,
+            scd_threshold: Amperes(200),
+            ocd_delay: OCDDelay::_1280ms,
+            ocd_threshold: Amperes(100),
+            uv_delay: UVDelay::_4s,
+            uv_threshold: MilliVolts(2000),
+            ov_delay: OVDelay::_4s,
+            ov_threshold: MilliVolts(4175),
+            cc_cfg: CC_CFG_RECOMMENDED,
+            ov_hysteresis: DEFAULT_OV_HYSTERESIS,
+            uv_hysteresis: DEFAULT_UV_HYSTERESIS
+        }
+    }
+
+    #[test]
+    fn it_works() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        let config = Config {
+            shunt: MicroOhms(667),
+            scd_delay: SCDDelay::_400uS,
+            scd_threshold: Amperes(200),
+            ocd_delay: OCDDelay::_1280ms,
+            ocd_threshold: Amperes(100),
+            uv_delay: UVDelay::_4s,
+            uv_threshold: MilliVolts(2000),
+            ov_delay: OVDelay::_4s,
+            ov_threshold: MilliVolts(4175),
+            cc_cfg: CC_CFG_RECOMMENDED,
+            ov_hysteresis: DEFAULT_OV_HYSTERESIS,
+            uv_hysteresis: DEFAULT_UV_HYSTERESIS
+        };
+        match bq769x0.init(&mut i2c, &config) {
+            Ok(actual) => {
+                std::println!("bq769x0 init ok");
+                std::println!("adc gain:{}uV/LSB offset:{}mV", bq769x0.adc_gain(), bq769x0.adc_offset());
+                std::println!("SCD: {}, OCD: {}, range: {:?}", actual.scd_threshold, actual.ocd_threshold, actual.ocdscd_range_used);
+                std::println!("UV: {}, OV: {}", actual.uv_threshold, actual.ov_threshold);
+            }
+            Err(e) => {
+                std::println!("bq769x0 init err: {:?}", e);
+            }
+        }
+    }
+
+    #[test]
+    fn millivolts_sub_does_not_underflow() {
+        use crate::*;
+
+        assert_eq!(MilliVolts(4100) - MilliVolts(3900), MilliVolts(200));
+        assert_eq!(MilliVolts(3900) - MilliVolts(4100), MilliVolts(0));
+        assert_eq!(MilliVolts(3900).saturating_sub(MilliVolts(4100)), MilliVolts(0));
+        assert_eq!(MilliVolts(3900).checked_sub(MilliVolts(4100)), None);
+        assert_eq!(MilliVolts(4100).checked_sub(MilliVolts(3900)), Some(MilliVolts(200)));
+    }
+
+    #[test]
+    fn min_max_delta_cells() {
+        use crate::*;
+
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 4, false).unwrap();
+        assert!(matches!(bq769x0.min_cell(), Err(Error::Uninitialized)));
+        assert!(matches!(bq769x0.cell_delta(), Err(Error::Uninitialized)));
+
+        // only the first cell_count cells are considered
+        bq769x0.cells = [MilliVolts(3700), MilliVolts(3650), MilliVolts(3810), MilliVolts(3720), MilliVolts(100)];
+        bq769x0.cells_read = true;
+        assert_eq!(bq769x0.min_cell().unwrap(), (1, MilliVolts(3650)));
+        assert_eq!(bq769x0.max_cell().unwrap(), (2, MilliVolts(3810)));
+        assert_eq!(bq769x0.cell_delta().unwrap(), MilliVolts(160));
+    }
+
+    #[test]
+    fn pack_voltage_discrepancy_detects_broken_sense_wire() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 4, false).unwrap();
+        assert!(matches!(bq769x0.cells_sum(), Err(Error::Uninitialized)));
+        bq769x0.adc_gain = 380;
+        bq769x0.adc_offset = 0;
+        bq769x0.init_complete = true;
+        // 4 * 380uV * 9868 = 14999mV
+        i2c.regs[0x2a] = (9868u16 >> 8) as u8;
+        i2c.regs[0x2b] = (9868u16 & 0xff) as u8;
+
+        bq769x0.cells = [MilliVolts(3750), MilliVolts(3750), MilliVolts(3760), MilliVolts(3740), MilliVolts(0)];
+        bq769x0.cells_read = true;
+        assert_eq!(bq769x0.cells_sum().unwrap(), MilliVolts(15000));
+        assert_eq!(bq769x0.pack_voltage_discrepancy(&mut i2c, MilliVolts(100)).unwrap(), None);
+
+        bq769x0.cells[2] = MilliVolts(2500);
+        assert_eq!(bq769x0.pack_voltage_discrepancy(&mut i2c, MilliVolts(100)).unwrap(), Some(MilliVolts(1259)));
+    }
+
+    #[test]
+    fn sys_ctrl_register_view() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        i2c.regs[0x04] = 0b0001_1000;
+        i2c.regs[0x05] = 0b0100_0001;
+        assert_eq!(bq769x0.read_sys_ctrl1(&mut i2c).unwrap(), SysCtrl1::ADC_EN | SysCtrl1::TEMP_SEL);
+        assert_eq!(bq769x0.read_sys_ctrl2(&mut i2c).unwrap(), SysCtrl2::CC_EN | SysCtrl2::CHG_ON);
+
+        let written = bq769x0.modify_sys_ctrl2(&mut i2c, |flags| {
+            flags.insert(SysCtrl2::DSG_ON);
+            flags.remove(SysCtrl2::CHG_ON);
+        }).unwrap();
+        assert_eq!(written, SysCtrl2::CC_EN | SysCtrl2::DSG_ON);
+        assert_eq!(i2c.regs[0x05], 0b0100_0010);
+
+        bq769x0.modify_sys_ctrl1(&mut i2c, |flags| flags.remove(SysCtrl1::TEMP_SEL)).unwrap();
+        assert_eq!(i2c.regs[0x04], 0b0001_0000);
+    }
+
+    #[test]
+    fn read_telemetry_matches_individual_reads() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 4, false).unwrap();
+        assert!(matches!(bq769x0.read_telemetry(&mut i2c), Err(Error::Uninitialized)));
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        for (i, b) in (0x0cu8..0x34).enumerate() {
+            i2c.regs[b as usize] = 0x10 + i as u8;
+        }
+        i2c.regs[0x32] = 0xff; // negative current
+        i2c.regs[0x04] = 0b0000_1000; // external thermistor
+
+        let telemetry = bq769x0.read_telemetry(&mut i2c).unwrap();
+        assert_eq!(telemetry.cells(), bq769x0.cell_voltages(&mut i2c).unwrap());
+        assert_eq!(telemetry.voltage, bq769x0.voltage(&mut i2c).unwrap());
+        assert_eq!(telemetry.current, bq769x0.current(&mut i2c).unwrap());
+        assert!(telemetry.current.0 < 0);
+        assert!(telemetry.temperature == bq769x0.temperature(&mut i2c).unwrap());
+    }
+
+    #[test]
+    fn temperature_all_per_variant() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        for (i, b) in (0x2cu8..0x32).enumerate() {
+            i2c.regs[b as usize] = i as u8 + 1;
+        }
+        let ts = |hi: u8, lo: u8| Temperature::InternalDie(DegreesCentigrade(25 - (((hi as i32) << 8 | lo as i32) * 382 - 1_200_000) / 4200));
+
+        let mut bq76920 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq76920.init(&mut i2c, &test_config()).unwrap();
+        let t = bq76920.temperature_all(&mut i2c).unwrap();
+        assert_eq!(t.len(), BQ769x0::<BQ76920>::TS_INPUTS);
+        assert!(t[0] == ts(1, 2));
+
+        let mut bq76930 = BQ769x0::<BQ76930>::new(0x08, 10, false).unwrap();
+        bq76930.init(&mut i2c, &test_config()).unwrap();
+        let t = bq76930.temperature_all(&mut i2c).unwrap();
+        assert_eq!(t.len(), BQ769x0::<BQ76930>::TS_INPUTS);
+        assert!(t[0] == ts(1, 2));
+        assert!(t[1] == ts(3, 4));
+
+        let mut bq76940 = BQ769x0::<BQ76940>::new(0x08, 15, false).unwrap();
+        bq76940.init(&mut i2c, &test_config()).unwrap();
+        let t = bq76940.temperature_all(&mut i2c).unwrap();
+        assert_eq!(t.len(), BQ769x0::<BQ76940>::TS_INPUTS);
+        assert!(t[0] == ts(1, 2) && t[1] == ts(3, 4) && t[2] == ts(5, 6));
+    }
+
+    #[test]
+    fn config_builder() {
+        use crate::*;
+
+        let config = Config::builder()
+            .shunt(MicroOhms(667))
+            .scd(Amperes(200), SCDDelay::_400uS)
+            .ocd(Amperes(100), OCDDelay::_1280ms)
+            .uv_threshold(MilliVolts(2000))
+            .ov_threshold(MilliVolts(4175))
+            .build();
+        assert_eq!(config.shunt, MicroOhms(667));
+        assert_eq!(config.scd_threshold, Amperes(200));
+        assert_eq!(config.scd_delay.bits(), SCDDelay::_400uS.bits());
+        assert_eq!(config.ocd_threshold, Amperes(100));
+        assert_eq!(config.ocd_delay.bits(), OCDDelay::_1280ms.bits());
+        assert_eq!(config.uv_delay.bits(), UVDelay::_1s.bits());
+        assert_eq!(config.ov_threshold, MilliVolts(4175));
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert!(bq769x0.init(&mut i2c, &ConfigBuilder::default().build()).is_ok());
+    }
+
+    #[test]
+    fn ocdscd_range_mismatch_diagnostic() {
+        use crate::*;
+
+        // SCD 33mV (lower only), OCD 61mV (upper only)
+        let config = Config::builder()
+            .shunt(MicroOhms(1000))
+            .scd(Amperes(30), SCDDelay::_70uS)
+            .ocd(Amperes(60), OCDDelay::_8ms)
+            .build();
+        match config.check_ocdscd_range() {
+            Err(Error::OCDSCDRangeMismatch(conflict)) => {
+                assert_eq!(conflict.scd_range, OCDSCDRange::Lower);
+                assert_eq!(conflict.ocd_range, OCDSCDRange::Upper);
+                assert_eq!(conflict.suggested_scd_threshold, Amperes(44));
+                assert_eq!(conflict.suggested_ocd_threshold, Amperes(50));
+            }
+            r => panic!("unexpected {:?}", r)
+        }
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert!(matches!(bq769x0.init(&mut i2c, &config), Err(Error::OCDSCDRangeMismatch(_))));
+
+        // SCD 155mV (upper only), OCD 11mV (lower only)
+        let config = Config::builder()
+            .shunt(MicroOhms(1000))
+            .scd(Amperes(150), SCDDelay::_70uS)
+            .ocd(Amperes(10), OCDDelay::_8ms)
+            .build();
+        match config.check_ocdscd_range() {
+            Err(Error::OCDSCDRangeMismatch(conflict)) => {
+                assert_eq!(conflict.scd_range, OCDSCDRange::Upper);
+                assert_eq!(conflict.ocd_range, OCDSCDRange::Lower);
+                assert_eq!(conflict.suggested_scd_threshold, Amperes(100));
+                assert_eq!(conflict.suggested_ocd_threshold, Amperes(17));
+            }
+            r => panic!("unexpected {:?}", r)
+        }
+
+        let config = Config::builder()
+            .shunt(MicroOhms(1000))
+            .scd(Amperes(150), SCDDelay::_70uS)
+            .ocd(Amperes(60), OCDDelay::_8ms)
+            .build();
+        assert_eq!(config.check_ocdscd_range().unwrap(), OCDSCDRange::Upper);
+    }
+
+    #[test]
+    fn staged_init() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert!(matches!(bq769x0.program_protection(&mut i2c, &test_config()), Err(Error::Uninitialized)));
+        bq769x0.read_adc_characteristics(&mut i2c).unwrap();
+        assert!(!bq769x0.is_initialized());
+        bq769x0.program_protection(&mut i2c, &test_config()).unwrap();
+        assert!(bq769x0.is_initialized());
+        assert_eq!(i2c.regs[0x05] & 0b0100_0000, 0);
+        bq769x0.enable_coulomb_counter(&mut i2c).unwrap();
+        assert_eq!(i2c.regs[0x05] & 0b0100_0000, 0b0100_0000);
+    }
+
+    #[test]
+    fn cell_voltages_raw_vs_converted() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 4, false).unwrap();
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        let readings = [0x1f00u16, 0x1f10, 0x0000, 0x1f20, 0x1f30];
+        for (i, r) in readings.iter().enumerate() {
+            i2c.regs[0x0c + i * 2] = (r >> 8) as u8;
+            i2c.regs[0x0c + i * 2 + 1] = (r & 0xff) as u8;
+        }
+        let raw = bq769x0.cell_voltages_raw(&mut i2c).unwrap();
+        // 4 cells: VC4 is unused and takes the VC5 reading
+        assert_eq!(raw[..4], [0x1f00, 0x1f10, 0x0000, 0x1f30]);
+        let converted: [MilliVolts; 4] = [
+            bq769x0.adc_transfer_function().apply(raw[0]),
+            bq769x0.adc_transfer_function().apply(raw[1]),
+            bq769x0.adc_transfer_function().apply(raw[2]),
+            bq769x0.adc_transfer_function().apply(raw[3]),
+        ];
+        assert_eq!(bq769x0.cell_voltages(&mut i2c).unwrap(), &converted);
+    }
+
+    #[test]
+    fn convert_adc_known_gain_offset() {
+        use crate::*;
+
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.adc_gain = 380;
+        bq769x0.adc_offset = 40;
+        assert_eq!(bq769x0.convert_adc(10000), MilliVolts(3840));
+        bq769x0.adc_offset = -20;
+        assert_eq!(bq769x0.convert_adc(10000), MilliVolts(3780));
+        assert_eq!(bq769x0.convert_adc(0x1000), MilliVolts(1536));
+    }
+
+    #[test]
+    fn current_when_ready_polls_cc_ready() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        i2c.regs[0x32] = 0x00;
+        i2c.regs[0x33] = 0x64;
+        let mut delay = DummyDelay { total_ms: 0 };
+        i2c.scripted_reads.push_back((0x00, std::vec![0x00]));
+        i2c.scripted_reads.push_back((0x00, std::vec![0x80]));
+        assert_eq!(bq769x0.current_when_ready(&mut i2c, &mut delay, CC_SAMPLE_PERIOD_MS).unwrap(), MilliAmperes(100 * 8440 / 667));
+        assert!(i2c.scripted_reads.is_empty());
+        assert_eq!(i2c.regs[0x00] & 0x80, 0);
+        assert_eq!(delay.total_ms, CC_POLL_INTERVAL_MS as u32);
+
+        // CC_READY never set: SYS_STAT is read at 0, 10 and 20ms
+        delay.total_ms = 0;
+        i2c.reads = 0;
+        assert!(matches!(bq769x0.current_when_ready(&mut i2c, &mut delay, 20), Err(Error::Timeout)));
+        assert_eq!((i2c.reads, delay.total_ms), (3, 20));
+    }
+
+    #[test]
+    fn current_polarity() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        i2c.regs[0x32] = 0xff;
+        i2c.regs[0x33] = 0x9c; // -100
+        assert_eq!(bq769x0.current_polarity(), CurrentPolarity::ChargePositive);
+        assert_eq!(bq769x0.current(&mut i2c).unwrap(), MilliAmperes(-100 * 8440 / 667));
+        bq769x0.set_current_polarity(CurrentPolarity::DischargePositive);
+        assert_eq!(bq769x0.current(&mut i2c).unwrap(), MilliAmperes(100 * 8440 / 667));
+    }
+
+    #[test]
+    fn calibrate_current_offset_removes_bias() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        // 7, 9, 8 LSB at zero load -> 88, 113, 101 mA
+        for cc in [7u8, 9, 8].iter() {
+            i2c.scripted_reads.push_back((0x00, std::vec![0x80]));
+            i2c.scripted_reads.push_back((0x32, std::vec![0x00, *cc]));
+        }
+        let mut delay = DummyDelay { total_ms: 0 };
+        let offset = bq769x0.calibrate_current_offset(&mut i2c, &mut delay, 3, CC_SAMPLE_PERIOD_MS).unwrap();
+        assert_eq!(offset, MilliAmperes((88 + 113 + 101) / 3));
+        assert_eq!(bq769x0.current_offset(), offset);
+
+        i2c.regs[0x32] = 0x00;
+        i2c.regs[0x33] = 8;
+        assert_eq!(bq769x0.current(&mut i2c).unwrap(), MilliAmperes(101 - 100));
+
+        // measured offset still applies after flipping the sign convention
+        bq769x0.set_current_polarity(CurrentPolarity::DischargePositive);
+        assert_eq!(bq769x0.current(&mut i2c).unwrap(), MilliAmperes(-(101 - 100)));
+        bq769x0.set_current_polarity(CurrentPolarity::ChargePositive);
+
+        bq769x0.set_current_offset(MilliAmperes(0));
+        assert_eq!(bq769x0.current(&mut i2c).unwrap(), MilliAmperes(101));
+
+        bq769x0.set_current_offset(offset);
+        assert!(matches!(bq769x0.calibrate_current_offset(&mut i2c, &mut delay, 0, CC_SAMPLE_PERIOD_MS), Err(Error::ZeroSamples)));
+        assert_eq!(bq769x0.current_offset(), offset);
+    }
+
+    #[test]
+    fn power_and_energy() {
+        use crate::*;
+
+        assert_eq!(MilliWatts::from_voltage_current(MilliVolts(14800), MilliAmperes(2000)), MilliWatts(29600));
+        assert_eq!(MilliWatts::from_voltage_current(MilliVolts(14800), MilliAmperes(-500)), MilliWatts(-7400));
+        assert_eq!(MilliWatts::from_voltage_current(MilliVolts(60000), MilliAmperes(200_000)), MilliWatts(12_000_000));
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        i2c.regs[0x33] = 79; // 999mA
+        let v = bq769x0.voltage(&mut i2c).unwrap();
+        assert_eq!(bq769x0.power(&mut i2c).unwrap(), MilliWatts((v.0 * 999 / 1000) as i32));
+
+        let mut acc = EnergyAccumulator::new();
+        for _ in 0..4 {
+            acc.add(MilliWatts(36_000), 250); // 36W for 1s
+        }
+        assert_eq!(acc.energy(), MilliWattHours(10));
+        acc.add(MilliWatts(-36_000), 500);
+        assert_eq!(acc.energy(), MilliWattHours(5));
+        acc.reset();
+        assert_eq!(acc.energy(), MilliWattHours(0));
+    }
+
+    #[test]
+    fn wake_from_ship_mode() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut delay = DummyDelay { total_ms: 0 };
+        let mut pin = DummyPin { history: std::vec::Vec::new() };
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        bq769x0.ship_enter(&mut i2c).unwrap();
+
+        bq769x0.wake_with_boot_pin(&mut i2c, &mut pin, &mut delay).unwrap();
+        assert_eq!(pin.history, [true, false]);
+        assert_eq!(delay.total_ms, (BOOT_PULSE_MS + WAKE_BOOT_MS) as u32);
+        assert!(!bq769x0.is_initialized());
+        // communication check pattern landed in the test register
+        assert_eq!(i2c.regs[0x0a], 0xaa);
+
+        i2c.nack = true;
+        assert!(matches!(bq769x0.wake(&mut i2c, &mut delay), Err(Error::I2CError)));
+    }
+
+    #[test]
+    fn reset_to_known_state() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        bq769x0.charge(&mut i2c, true).unwrap();
+        bq769x0.discharge(&mut i2c, true).unwrap();
+        i2c.regs[0x00] = 0b1011_0101;
+        i2c.regs[0x0b] = 0x00;
+
+        bq769x0.reset(&mut i2c).unwrap();
+        assert_eq!(i2c.regs[0x00], 0x00);
+        assert_eq!(i2c.regs[0x05], 0b0100_0000); // CC_EN kept, CHG/DSG off
+        assert_eq!(i2c.regs[0x0b], 0x19);
+        assert!(!bq769x0.is_initialized());
+        assert!(matches!(bq769x0.cell_voltages(&mut i2c), Err(Error::Uninitialized)));
+    }
+
+    #[test]
+    fn cc_cfg_reaches_register() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        assert_eq!(i2c.regs[0x0b], 0x19);
+
+        let config = Config { cc_cfg: 0x1a, ..test_config() };
+        bq769x0.init(&mut i2c, &config).unwrap();
+        assert_eq!(i2c.regs[0x0b], 0x1a);
+        assert_eq!(Config::builder().build().cc_cfg, CC_CFG_RECOMMENDED);
+    }
+
+    #[test]
+    fn faults_cleared_one_by_one() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        i2c.regs[0x00] = 0b1000_1011;
+        assert_eq!(bq769x0.faults(&mut i2c).unwrap(), SysStat::UNDERVOLTAGE | SysStat::SHORTCIRCUIT | SysStat::OVERCURRENT);
+
+        bq769x0.clear_fault(&mut i2c, SysStat::OVERCURRENT).unwrap();
+        assert_eq!(i2c.regs[0x00], 0b1000_1010);
+        bq769x0.clear_fault(&mut i2c, SysStat::UNDERVOLTAGE).unwrap();
+        assert_eq!(bq769x0.faults(&mut i2c).unwrap(), SysStat::SHORTCIRCUIT);
+        bq769x0.clear_fault(&mut i2c, SysStat::SHORTCIRCUIT).unwrap();
+        assert!(bq769x0.faults(&mut i2c).unwrap().is_empty());
+        assert_eq!(i2c.regs[0x00], 0b1000_0000);
+    }
+
+    #[test]
+    fn sys_stat_is_sys_stat() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        i2c.regs[0x00] = 0b1000_0000;
+        let stat = bq769x0.sys_stat(&mut i2c).unwrap();
+        assert_eq!(stat, SysStat::CC_READY);
+        assert!(stat.is_ok());
+
+        i2c.regs[0x00] = 0b1000_0010;
+        let stat = bq769x0.sys_stat(&mut i2c).unwrap();
+        assert!(!stat.is_ok());
+        bq769x0.sys_stat_reset(&mut i2c, stat & SysStat::SHORTCIRCUIT).unwrap();
+        assert!(bq769x0.sys_stat(&mut i2c).unwrap().is_ok());
+    }
+
+    #[test]
+    fn cells_iter_skips_unused_inputs() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 4, false).unwrap();
+        assert_eq!(bq769x0.cells_iter().count(), 0);
+        assert_eq!(bq769x0.cell(0), None);
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        bq769x0.adc_gain = 1000;
+        bq769x0.adc_offset = 0;
+        for (i, mv) in [3601u16, 3602, 3603, 10, 3605].iter().enumerate() {
+            i2c.regs[0x0c + i * 2] = (mv >> 8) as u8;
+            i2c.regs[0x0c + i * 2 + 1] = (mv & 0xff) as u8;
+        }
+        bq769x0.cell_voltages(&mut i2c).unwrap();
+        let cells: std::vec::Vec<(usize, MilliVolts)> = bq769x0.cells_iter().collect();
+        assert_eq!(cells, [(0, MilliVolts(3601)), (1, MilliVolts(3602)), (2, MilliVolts(3603)), (4, MilliVolts(3605))]);
+        assert_eq!(bq769x0.cell(2), Some(MilliVolts(3603)));
+        assert_eq!(bq769x0.cell(3), None);
+        assert_eq!(bq769x0.cell(4), Some(MilliVolts(3605)));
+        assert_eq!(bq769x0.cell(5), None);
+    }
+
+    #[test]
+    fn detect_variant_mismatch() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        i2c.reserved_regs.extend([0x02, 0x03].iter());
+        let mut bq76930 = BQ769x0::<BQ76930>::new(0x08, 10, false).unwrap();
+        assert!(matches!(bq76930.detect_variant(&mut i2c), Err(Error::VariantMismatch(BQ76920))));
+        let mut bq76920 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert_eq!(bq76920.detect_variant(&mut i2c).unwrap(), BQ76920);
+
+        let mut i2c = DummyI2C::new();
+        i2c.reserved_regs.push(0x03);
+        assert_eq!(bq76930.detect_variant(&mut i2c).unwrap(), BQ76930);
+        assert_eq!(i2c.regs[0x02], 0);
+
+        let mut i2c = DummyI2C::new();
+        i2c.regs[0x03] = 0b0_0100; // balancing already active, left untouched
+        let mut bq76940 = BQ769x0::<BQ76940>::new(0x08, 15, false).unwrap();
+        assert_eq!(bq76940.detect_variant(&mut i2c).unwrap(), BQ76940);
+        assert_eq!(i2c.regs[0x03], 0b0_0100);
+        assert!(matches!(bq76930.detect_variant(&mut i2c), Err(Error::VariantMismatch(BQ76940))));
+    }
+
+    #[test]
+    fn detect_variant_restores_after_failed_read_back() {
+        use crate::*;
+        use crate::mock::{I2cMock, Transaction};
+
+        let mut i2c = I2cMock::new(&[
+            Transaction::read(0x08, regs::CELLBAL2, &[0]),
+            Transaction::write(0x08, regs::CELLBAL2, &[0b0_0001]),
+            Transaction::read(0x08, regs::CELLBAL2, &[0]).with_nack(),
+            Transaction::write(0x08, regs::CELLBAL2, &[0]),
+        ]);
+        let mut bq76930 = BQ769x0::<BQ76930>::new(0x08, 10, false).unwrap();
+        assert!(matches!(bq76930.detect_variant(&mut i2c), Err(Error::I2CError)));
+        i2c.done();
+    }
+
+    #[test]
+    fn balancing_cells_per_variant() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        i2c.regs[0x01] = 0b1_0001;
+        i2c.regs[0x02] = 0b0_0100;
+        i2c.regs[0x03] = 0b0_1010;
+
+        let mut bq76920 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        let cells = bq76920.balancing_cells(&mut i2c).unwrap();
+        assert_eq!(cells, CellMask(0b1_0001));
+        assert_eq!(cells.iter().collect::<std::vec::Vec<_>>(), [0, 4]);
+
+        let mut bq76930 = BQ769x0::<BQ76930>::new(0x08, 10, false).unwrap();
+        let cells = bq76930.balancing_cells(&mut i2c).unwrap();
+        assert_eq!(cells.iter().collect::<std::vec::Vec<_>>(), [0, 4, 7]);
+
+        let mut bq76940 = BQ769x0::<BQ76940>::new(0x08, 15, false).unwrap();
+        let cells = bq76940.balancing_cells(&mut i2c).unwrap();
+        assert_eq!(cells.iter().collect::<std::vec::Vec<_>>(), [0, 4, 7, 11, 13]);
+        assert!(cells.contains(13) && !cells.contains(12));
+    }
+
+    #[test]
+    fn enable_balancing_upper_cells() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq76940 = BQ769x0::<BQ76940>::new(0x08, 15, false).unwrap();
+        bq76940.enable_balancing(&mut i2c, 0b10100_01001_00001).unwrap();
+        assert_eq!(i2c.regs[0x01], 0b0_0001);
+        assert_eq!(i2c.regs[0x02], 0b0_1001);
+        assert_eq!(i2c.regs[0x03], 0b1_0100);
+        assert_eq!(bq76940.balancing_cells(&mut i2c).unwrap(), CellMask(0b10100_01001_00001));
+
+        let mut i2c = DummyI2C::new();
+        let mut bq76930 = BQ769x0::<BQ76930>::new(0x08, 10, false).unwrap();
+        bq76930.enable_balancing(&mut i2c, 0b10100_01001_00001).unwrap();
+        assert_eq!(i2c.regs[0x02], 0b0_1001);
+        assert_eq!(i2c.regs[0x03], 0);
+    }
+
+    #[test]
+    fn overtemperature_disables_fets() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq76930 = BQ769x0::<BQ76930>::new(0x08, 10, false).unwrap();
+        bq76930.init_complete = true;
+        // linear stand-in for an NTC curve: 1.5V at 25degC, -20mV/degC
+        let ntc = |microvolts: i32| DegreesCentigrade(25 + (1_500_000 - microvolts) / 20_000);
+        i2c.regs[0x05] = (SysCtrl2::CHG_ON | SysCtrl2::DSG_ON).bits();
+        // die sensor, voltage drops as it heats up
+        i2c.regs[0x2c] = 0x0c; // 1.1735V, 31degC
+        i2c.regs[0x2e] = 0x0b; // 1.0757V, 54degC, TS2 is the hot one
+        let hottest = DegreesCentigrade(54);
+
+        let status = bq76930.check_overtemperature(&mut i2c, DegreesCentigrade(55), ntc).unwrap();
+        assert!(status.is_ok());
+        assert_eq!(status.hottest, hottest);
+        assert_eq!(i2c.regs[0x05], (SysCtrl2::CHG_ON | SysCtrl2::DSG_ON).bits());
+
+        let limits = OvertemperatureLimits { charge: DegreesCentigrade(45), discharge: DegreesCentigrade(60) };
+        let status = bq76930.check_overtemperature_limits(&mut i2c, limits, ntc).unwrap();
+        assert_eq!(status, OvertemperatureStatus { charge: true, discharge: false, hottest });
+        assert_eq!(i2c.regs[0x05], SysCtrl2::DSG_ON.bits());
+
+        let status = bq76930.check_overtemperature(&mut i2c, DegreesCentigrade(45), ntc).unwrap();
+        assert!(status.charge && status.discharge);
+        assert_eq!(i2c.regs[0x05], 0);
+
+        // thermistors go through the conversion, the lowest voltage is the hottest NTC
+        bq76930.set_temperature_source(&mut i2c, TemperatureSource::ExternalThermistor).unwrap();
+        i2c.regs[0x05] = (SysCtrl2::CHG_ON | SysCtrl2::DSG_ON).bits();
+        i2c.regs[0x2e] = 0x10; // 1.5647V, 22degC
+        let status = bq76930.check_overtemperature(&mut i2c, DegreesCentigrade(45), ntc).unwrap();
+        assert!(status.is_ok());
+        assert_eq!(status.hottest, DegreesCentigrade(41));
+        let status = bq76930.check_overtemperature(&mut i2c, DegreesCentigrade(40), ntc).unwrap();
+        assert!(status.charge && status.discharge);
+    }
+
+    #[test]
+    fn calibration_round_trip() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        bq769x0.set_current_offset(MilliAmperes(-12));
+        let calibration = bq769x0.export_calibration();
+        assert_eq!(calibration, Calibration { adc_gain: 378, adc_offset: 43, shunt: MicroOhms(667), current_offset: MilliAmperes(-12) });
+
+        // Warm boot: no reads from the factory registers needed
+        let mut i2c = DummyI2C::new();
+        i2c.regs[0x50] = 0;
+        i2c.regs[0x51] = 0;
+        i2c.regs[0x59] = 0;
+        let mut restored = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        restored.import_calibration(&calibration);
+        assert_eq!(restored.export_calibration(), calibration);
+        restored.program_protection(&mut i2c, &test_config()).unwrap();
+        assert_eq!(restored.adc_gain(), 378);
+        assert_eq!(restored.adc_offset(), 43);
+        assert_eq!(restored.current_offset(), MilliAmperes(-12));
+    }
+
+    #[test]
+    fn unit_operators() {
+        use crate::*;
+
+        assert_eq!(MilliVolts(3900) + MilliVolts(100), MilliVolts(4000));
+        assert_eq!(MilliVolts(3900) * 4, MilliVolts(15600));
+        assert_eq!(MilliVolts(15600) / 4, MilliVolts(3900));
+        assert_eq!(MilliVolts(u32::MAX).checked_add(MilliVolts(1)), None);
+
+        assert_eq!(MilliAmperes(500) - MilliAmperes(800), MilliAmperes(-300));
+        assert_eq!(MilliAmperes(500) + MilliAmperes(-800), MilliAmperes(-300));
+        assert_eq!(-MilliAmperes(500), MilliAmperes(-500));
+        assert_eq!(MilliAmperes(-500) * 3, MilliAmperes(-1500));
+        assert_eq!(MilliAmperes(-1500) / 3, MilliAmperes(-500));
+        assert_eq!(MilliAmperes(i32::MIN).checked_sub(MilliAmperes(1)), None);
+
+        assert_eq!(Amperes(20) - Amperes(50), Amperes(0));
+        assert_eq!(Amperes(20).checked_sub(Amperes(50)), None);
+        assert_eq!(Amperes(20) + Amperes(30), Amperes(50));
+        assert_eq!(Amperes(20) * 2 / 4, Amperes(10));
+
+        assert_eq!(MicroOhms(1000) + MicroOhms(500), MicroOhms(1500));
+        assert_eq!(MicroOhms(1000) / 2, MicroOhms(500));
+        assert_eq!(MicroOhms(500) - MicroOhms(1000), MicroOhms(0));
+    }
+
+    #[test]
+    fn unit_iterator_sum() {
+        use crate::*;
+
+        let cells = [MilliVolts(3700), MilliVolts(3710), MilliVolts(3690)];
+        let pack: MilliVolts = cells.iter().copied().sum();
+        assert_eq!(pack, MilliVolts(11100));
+
+        let samples = [MilliAmperes(-200), MilliAmperes(150), MilliAmperes(-50)];
+        let total: MilliAmperes = samples.iter().copied().sum();
+        assert_eq!(total, MilliAmperes(-100));
+        assert_eq!(core::iter::empty::<MilliAmperes>().sum::<MilliAmperes>(), MilliAmperes(0));
+    }
+
+    #[test]
+    fn telemetry_serde_round_trip() {
+        use crate::*;
+
+        let telemetry = Telemetry::<BQ76940> {
+            cells: [MilliVolts(3700); BQ76940],
+            cell_count: 13,
+            voltage: MilliVolts(48100),
+            temperature: Temperature::ExternalThermistor(DegreesCentigrade(31)),
+            current: MilliAmperes(-2500),
+        };
+        let mut buf = [0u8; 128];
+        let bytes = postcard::to_slice(&telemetry, &mut buf).unwrap();
+        let decoded: Telemetry<BQ76940> = postcard::from_bytes(bytes).unwrap();
+        assert_eq!(decoded, telemetry);
+
+        let values = CalculatedValues {
+            ocdscd_range_used: OCDSCDRange::Upper,
+            scd_threshold: Amperes(200),
+            ocd_threshold: Amperes(100),
+            uv_threshold: MilliVolts(2000),
+            ov_threshold: MilliVolts(4175),
+            uv_recovery: MilliVolts(2100),
+            ov_recovery: MilliVolts(4075),
+            shunt: MicroOhms(667),
+            scd_delay: SCDDelay::_400uS,
+            ocd_delay: OCDDelay::_1280ms,
+            uv_delay: UVDelay::_4s,
+            ov_delay: OVDelay::_4s,
+            cc_cfg: CC_CFG_RECOMMENDED,
+        };
+        let bytes = postcard::to_slice(&values, &mut buf).unwrap();
+        assert_eq!(postcard::from_bytes::<CalculatedValues>(bytes).unwrap(), values);
+    }
+
+    #[test]
+    fn adc_gain_bit_packing() {
+        use crate::*;
+
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        let cases = [
+            (0x00, 0x00, 365),
+            (0x0c, 0xe0, 396), // all gain bits set
+            (0xf3, 0x1f, 365), // all other bits set
+            (0x08, 0x20, 365 + 0b10001),
+            (0x04, 0xa0, 365 + 0b01101),
+        ];
+        for &(adcgain1, adcgain2, gain) in cases.iter() {
+            let mut i2c = DummyI2C::new();
+            i2c.regs[0x50] = adcgain1;
+            i2c.regs[0x59] = adcgain2;
+            bq769x0.read_adc_characteristics(&mut i2c).unwrap();
+            assert_eq!(bq769x0.adc_gain(), gain);
+        }
+    }
+
+    #[test]
+    fn configure_outputs_single_transaction() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        i2c.regs[0x05] = SysCtrl2::DELAY_DIS.bits();
+        let sys_ctrl2 = bq769x0.configure_outputs(&mut i2c, true, true, CoulombCounterMode::Continuous).unwrap();
+        assert_eq!(sys_ctrl2, SysCtrl2::DELAY_DIS | SysCtrl2::CC_EN | SysCtrl2::DSG_ON | SysCtrl2::CHG_ON);
+        assert_eq!(i2c.regs[0x05], sys_ctrl2.bits());
+        assert_eq!((i2c.reads, i2c.writes), (1, 1));
+
+        bq769x0.configure_outputs(&mut i2c, false, true, CoulombCounterMode::OneShot).unwrap();
+        assert_eq!(i2c.regs[0x05], (SysCtrl2::DELAY_DIS | SysCtrl2::CC_ONESHOT | SysCtrl2::DSG_ON).bits());
+        assert_eq!((i2c.reads, i2c.writes), (2, 2));
+    }
+
+    #[test]
+    fn connected_cell_map_per_variant() {
+        use crate::*;
+
+        let map = |bq: Option<BQ769x0<BQ76920>>| bq.unwrap().connected_cell_map();
+        assert_eq!(map(BQ769x0::new(0x08, 3, false)), CellMask(0b1_0011));
+        assert_eq!(map(BQ769x0::new(0x08, 4, false)), CellMask(0b1_0111));
+        assert_eq!(map(BQ769x0::new(0x08, 5, false)), CellMask(0b1_1111));
+
+        let map = |bq: Option<BQ769x0<BQ76930>>| bq.unwrap().connected_cell_map();
+        assert_eq!(map(BQ769x0::new(0x08, 6, false)), CellMask(0b10011_10011));
+        assert_eq!(map(BQ769x0::new(0x08, 7, false)), CellMask(0b10011_10111));
+        assert_eq!(map(BQ769x0::new(0x08, 9, false)), CellMask(0b10111_11111));
+        assert_eq!(map(BQ769x0::new(0x08, 10, false)), CellMask(0b11111_11111));
+
+        let map = |bq: Option<BQ769x0<BQ76940>>| bq.unwrap().connected_cell_map();
+        assert_eq!(map(BQ769x0::new(0x08, 9, false)), CellMask(0b10011_10011_10011));
+        assert_eq!(map(BQ769x0::new(0x08, 11, false)), CellMask(0b10011_10111_10111));
+        assert_eq!(map(BQ769x0::new(0x08, 13, false)), CellMask(0b10111_10111_11111));
+        assert_eq!(map(BQ769x0::new(0x08, 15, false)), CellMask(0b11111_11111_11111));
+    }
+
+    #[test]
+    fn cell_voltages_follow_connected_cell_map() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq76930 = BQ769x0::<BQ76930>::new(0x08, 8, false).unwrap();
+        bq76930.init(&mut i2c, &test_config()).unwrap();
+        bq76930.adc_gain = 1000;
+        bq76930.adc_offset = 0;
+        for i in 0..10 {
+            i2c.regs[0x0d + i * 2] = i as u8 + 1; // VC(n) reads n mV
+        }
+        let cells = bq76930.cell_voltages(&mut i2c).unwrap();
+        let expected: std::vec::Vec<MilliVolts> = [1, 2, 3, 5, 6, 7, 8, 10].iter().map(|&mv| MilliVolts(mv)).collect();
+        assert_eq!(cells, &expected[..]);
+        assert_eq!(bq76930.cell(9), Some(MilliVolts(10)));
+        assert_eq!(bq76930.cell(8), None);
+
+        // All inputs used, nothing may be moved
+        let mut bq76930 = BQ769x0::<BQ76930>::new(0x08, 10, false).unwrap();
+        bq76930.init(&mut i2c, &test_config()).unwrap();
+        bq76930.adc_gain = 1000;
+        bq76930.adc_offset = 0;
+        let cells = bq76930.cell_voltages(&mut i2c).unwrap();
+        let expected: std::vec::Vec<MilliVolts> = (1..=10).map(MilliVolts).collect();
+        assert_eq!(cells, &expected[..]);
+    }
+
+    #[test]
+    fn ov_uv_recovery_thresholds() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        let values = bq769x0.init(&mut i2c, &test_config()).unwrap();
+        assert_eq!(values.ov_recovery, values.ov_threshold - DEFAULT_OV_HYSTERESIS);
+        assert_eq!(values.uv_recovery, values.uv_threshold + DEFAULT_UV_HYSTERESIS);
+        let config = Config { ov_hysteresis: MilliVolts(50), uv_hysteresis: MilliVolts(200), ..test_config() };
+        let custom = bq769x0.compute_config(&config).unwrap();
+        assert_eq!(custom.ov_recovery, values.ov_threshold - MilliVolts(50));
+        assert_eq!(custom.uv_recovery, values.uv_threshold + MilliVolts(200));
+        assert_eq!(custom.to_config().ov_hysteresis, MilliVolts(50));
+
+        let values = CalculatedValues {
+            ocdscd_range_used: OCDSCDRange::Upper,
+            scd_threshold: Amperes(200),
+            ocd_threshold: Amperes(100),
+            uv_threshold: MilliVolts(2800),
+            ov_threshold: MilliVolts(4200),
+            uv_recovery: MilliVolts(2900),
+            ov_recovery: MilliVolts(4100),
+            ..bq769x0.compute_config(&test_config()).unwrap()
+        };
+        assert!(!values.is_ov_recovered(MilliVolts(4150)));
+        assert!(values.is_ov_recovered(MilliVolts(4100)));
+        assert!(!values.is_uv_recovered(MilliVolts(2850)));
+        assert!(values.is_uv_recovered(MilliVolts(2900)));
+        assert!(values.is_recovered(MilliVolts(3700)));
+        assert!(!values.is_recovered(MilliVolts(4150)));
+        assert!(!values.is_recovered(MilliVolts(2850)));
+    }
+
+    #[test]
+    fn adc_and_cc_enabled_status() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert!(!bq769x0.is_adc_enabled(&mut i2c).unwrap());
+        assert!(!bq769x0.is_cc_enabled(&mut i2c).unwrap());
+
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        assert!(bq769x0.is_cc_enabled(&mut i2c).unwrap());
+        bq769x0.enable_adc(&mut i2c, true).unwrap();
+        assert!(bq769x0.is_adc_enabled(&mut i2c).unwrap());
+
+        bq769x0.coulomb_counter_mode(&mut i2c, CoulombCounterMode::OneShot).unwrap();
+        assert!(!bq769x0.is_cc_enabled(&mut i2c).unwrap());
+        bq769x0.enable_adc(&mut i2c, false).unwrap();
+        assert!(!bq769x0.is_adc_enabled(&mut i2c).unwrap());
+    }
+
+    #[test]
+    fn custom_cell_wiring() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq76930 = BQ769x0::<BQ76930>::new(0x08, 9, false).unwrap();
+        assert_eq!(bq76930.connected_cell_map(), CellMask(0b10111_11111));
+        bq76930.set_cell_wiring(CellWiring::Custom(CellMask(0b11111_10111))).unwrap();
+        assert_eq!(bq76930.connected_cell_map(), CellMask(0b11111_10111));
+
+        bq76930.init(&mut i2c, &test_config()).unwrap();
+        bq76930.adc_gain = 1000;
+        bq76930.adc_offset = 0;
+        for i in 0..10 {
+            i2c.regs[0x0d + i * 2] = i as u8 + 1;
+        }
+        let cells = bq76930.cell_voltages(&mut i2c).unwrap();
+        let expected: std::vec::Vec<MilliVolts> = [1, 2, 3, 5, 6, 7, 8, 9, 10].iter().map(|&mv| MilliVolts(mv)).collect();
+        assert_eq!(cells, &expected[..]);
+    }
+
+    #[test]
+    fn bad_cell_wiring_rejected() {
+        use crate::*;
+
+        let mut bq76930 = BQ769x0::<BQ76930>::new(0x08, 9, false).unwrap();
+        let mut rejected = |map: u16| matches!(bq76930.set_cell_wiring(CellWiring::Custom(CellMask(map))), Err(Error::InvalidCellWiring));
+        assert!(rejected(0b11111_11111)); // 10 cells, 9 configured
+        assert!(rejected(0b01111_11111)); // VC10 unused
+        assert!(rejected(0b11111_11011)); // VC4 used with VC3 shorted
+        assert!(rejected(0b1_11111_10011)); // beyond BQ76930 inputs
+        assert_eq!(bq76930.connected_cell_map(), CellMask(0b10111_11111));
+
+        let mut bq76940 = BQ769x0::<BQ76940>::new(0x08, 9, false).unwrap();
+        assert!(matches!(bq76940.set_cell_wiring(CellWiring::Custom(CellMask(0b11111_00011_10011))), Err(Error::InvalidCellWiring)));
+        bq76940.set_cell_wiring(CellWiring::Custom(CellMask(0b10011_10011_10011))).unwrap();
+        bq76940.set_cell_wiring(CellWiring::Spread).unwrap();
+        assert_eq!(bq76940.cell_wiring(), CellWiring::Spread);
+    }
+
+    #[test]
+    fn alert_override_toggle() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut pin = DummyPin { history: std::vec::Vec::new() };
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert!(!bq769x0.is_alert_overridden(&mut i2c).unwrap());
+
+        // device latches OVRD_ALERT once the line goes high
+        i2c.scripted_reads.push_back((0x00, std::vec![(SysStat::OVRD_ALERT | SysStat::CC_READY).bits()]));
+        assert!(bq769x0.assert_alert_override(&mut i2c, &mut pin).unwrap());
+        assert!(bq769x0.is_alert_overridden(&mut i2c).unwrap());
+
+        bq769x0.clear_alert_override(&mut i2c, &mut pin).unwrap();
+        assert_eq!(pin.history, [true, false]);
+        assert!(!bq769x0.is_alert_overridden(&mut i2c).unwrap());
+        assert_eq!(i2c.regs[0x00], SysStat::CC_READY.bits());
+    }
+
+    #[test]
+    fn register_addresses() {
+        use crate::regs::*;
+
+        assert_eq!([SYS_STAT, CELLBAL1, CELLBAL2, CELLBAL3, SYS_CTRL1, SYS_CTRL2], [0x00, 0x01, 0x02, 0x03, 0x04, 0x05]);
+        assert_eq!([PROTECT1, PROTECT2, PROTECT3, OV_TRIP, UV_TRIP, CC_CFG], [0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b]);
+        assert_eq!(VC1_HI, 0x0c);
+        assert_eq!([BAT_HI, BAT_LO, TS1_HI, TS2_HI, TS3_HI, CC_HI, CC_LO], [0x2a, 0x2b, 0x2c, 0x2e, 0x30, 0x32, 0x33]);
+        assert_eq!([ADCGAIN1, ADCOFFSET, ADCGAIN2], [0x50, 0x51, 0x59]);
+        assert_eq!(crate::TELEMETRY_LEN, 40);
+    }
+
+    #[test]
+    fn raw_transfer_limits() {
+        use crate::*;
+
+        for &use_crc in [false, true].iter() {
+            let mut i2c = DummyI2C::new();
+            let mut bq769x0 = BQ769x0::<BQ76940>::new(0x08, 15, use_crc).unwrap();
+            let mut buf = [0u8; MAX_READ_LEN + 1];
+
+            assert!(bq769x0.read_raw(&mut i2c, 0x0c, &mut buf[..0]).is_ok());
+            assert!(bq769x0.write_raw(&mut i2c, 0x0c, &buf[..0]).is_ok());
+            assert!(matches!(bq769x0.read_raw(&mut i2c, 0x0c, &mut buf), Err(Error::BufTooLarge)));
+            assert!(matches!(bq769x0.write_raw(&mut i2c, 0x0c, &buf[..MAX_WRITE_LEN + 1]), Err(Error::BufTooLarge)));
+            assert_eq!((i2c.reads, i2c.writes), (0, 0));
+
+            // CRC of the dummy register contents is not valid, only the length check matters here
+            assert!(!matches!(bq769x0.read_raw(&mut i2c, 0x0c, &mut buf[..MAX_READ_LEN]), Err(Error::BufTooLarge)));
+            assert!(bq769x0.write_raw(&mut i2c, 0x0c, &buf[..MAX_WRITE_LEN]).is_ok());
+            assert_eq!((i2c.reads, i2c.writes), (1, 1));
+        }
+    }
+
+    #[test]
+    fn crc_mismatch_retry() {
+        use crate::*;
+
+        let good = crc_block(&[(0x08 << 1) | 1], &[0x80]);
+        let mut bad = good.clone();
+        bad[1] ^= 0x01;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, true).unwrap();
+        assert_eq!(bq769x0.crc_retries(), 0);
+        i2c.scripted_reads.push_back((0x00, bad.clone()));
+        assert!(matches!(bq769x0.sys_stat(&mut i2c), Err(Error::CRCMismatch)));
+
+        bq769x0.set_crc_retries(2);
+        i2c.scripted_reads.push_back((0x00, bad.clone()));
+        i2c.scripted_reads.push_back((0x00, good.clone()));
+        i2c.reads = 0;
+        assert_eq!(bq769x0.sys_stat(&mut i2c).unwrap(), SysStat::CC_READY);
+        assert_eq!(i2c.reads, 2);
+
+        // retries exhausted
+        i2c.scripted_reads.extend([bad.clone(), bad.clone(), bad].iter().map(|b| (0x00, b.clone())));
+        assert!(matches!(bq769x0.sys_stat(&mut i2c), Err(Error::CRCMismatch)));
+        assert_eq!(i2c.reads, 5);
+    }
+
+    #[test]
+    fn crc_write_retry_after_nack() {
+        use crate::*;
+        use crate::mock::{I2cMock, Transaction};
+
+        let framed = crc_block(&[0x08 << 1, regs::CELLBAL1], &[0b1_0001]);
+        let mut i2c = I2cMock::new(&[
+            Transaction::write(0x08, regs::CELLBAL1, &framed).with_nack(),
+            Transaction::write(0x08, regs::CELLBAL1, &framed),
+        ]);
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, true).unwrap();
+        bq769x0.set_crc_retries(1);
+        bq769x0.write_raw(&mut i2c, regs::CELLBAL1, &[0b1_0001]).unwrap();
+        i2c.done();
+
+        // retries exhausted
+        let mut i2c = I2cMock::new(&[
+            Transaction::write(0x08, regs::CELLBAL1, &framed).with_nack(),
+            Transaction::write(0x08, regs::CELLBAL1, &framed).with_nack(),
+        ]);
+        assert!(matches!(bq769x0.write_raw(&mut i2c, regs::CELLBAL1, &[0b1_0001]), Err(Error::I2CError)));
+        i2c.done();
+    }
+
+    #[test]
+    fn crc_read_full_bq76940_cell_block() {
+        use crate::*;
+
+        let cells: std::vec::Vec<u8> = (0..15u16).flat_map(|cell| (0x1000 + cell * 0x11).to_be_bytes()).collect();
+        let block = crc_block(&[(0x08 << 1) | 1], &cells);
+        assert_eq!(block.len(), 60);
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76940>::new(0x08, 15, true).unwrap();
+        i2c.scripted_reads.push_back((0x0c, block.clone()));
+        let raw = bq769x0.cell_voltages_raw(&mut i2c).unwrap();
+        for (cell, r) in raw.iter().enumerate() {
+            assert_eq!(*r, 0x1000 + cell as u16 * 0x11);
+        }
+
+        // CRC of the very last byte
+        let mut corrupted = block.clone();
+        corrupted[59] ^= 0x01;
+        i2c.scripted_reads.push_back((0x0c, corrupted));
+        assert!(matches!(bq769x0.cell_voltages_raw(&mut i2c), Err(Error::CRCMismatch)));
+
+        // bus error is reported as such, not as a CRC mismatch of the stale buffer
+        i2c.nack = true;
+        assert!(matches!(bq769x0.cell_voltages_raw(&mut i2c), Err(Error::I2CError)));
+    }
+
+    #[test]
+    fn threshold_actual_current() {
+        use crate::*;
+
+        let (scd, actual) = SCDThreshold::from_current_actual(Amperes(95), MicroOhms(1000));
+        assert_eq!((scd as u8, actual), (100, Amperes(100)));
+        let (scd, actual) = SCDThreshold::from_current_actual(Amperes(200), MicroOhms(667));
+        assert_eq!((scd as u8, actual), (133, Amperes(199)));
+        let (scd, actual) = SCDThreshold::from_current_actual(Amperes(30), MicroOhms(2000));
+        assert_eq!((scd as u8, actual), (67, Amperes(33)));
+
+        let (ocd, actual) = OCDThreshold::from_current_actual(Amperes(20), MicroOhms(1000));
+        assert_eq!((ocd as u8, actual), (22, Amperes(22)));
+        let (ocd, actual) = OCDThreshold::from_current_actual(Amperes(100), MicroOhms(667));
+        assert_eq!((ocd as u8, actual), (67, Amperes(100)));
+        let (ocd, actual) = OCDThreshold::from_current_actual(Amperes(4), MicroOhms(500));
+        assert_eq!((ocd as u8, actual), (8, Amperes(16)));
+    }
+
+    #[test]
+    fn current_without_shunt() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        i2c.regs[0x00] = SysStat::CC_READY.bits();
+        i2c.regs[0x33] = 0x10;
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert!(matches!(bq769x0.current(&mut i2c), Err(Error::Uninitialized)));
+        assert!(matches!(bq769x0.current_when_ready(&mut i2c, &mut DummyDelay { total_ms: 0 }, 0), Err(Error::Uninitialized)));
+        assert!(matches!(bq769x0.calibrate_current_offset(&mut i2c, &mut DummyDelay { total_ms: 0 }, 1, 0), Err(Error::Uninitialized)));
+        assert!(matches!(bq769x0.power(&mut i2c), Err(Error::Uninitialized)));
+
+        let mut config = test_config();
+        config.shunt = MicroOhms(0);
+        assert!(matches!(config.check_ocdscd_range(), Err(Error::ZeroShunt)));
+        assert!(matches!(bq769x0.init(&mut i2c, &config), Err(Error::ZeroShunt)));
+        assert_eq!(SCDThreshold::from_current_actual(Amperes(100), MicroOhms(0)).1, Amperes(u32::MAX));
+
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        assert!(bq769x0.current(&mut i2c).is_ok());
+    }
+
+    #[test]
+    fn compute_config_offline() {
+        use crate::*;
+
+        let bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert!(matches!(bq769x0.compute_config(&test_config()), Err(Error::Uninitialized)));
+
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.import_calibration(&Calibration { adc_gain: 380, adc_offset: 0, shunt: MicroOhms(0), current_offset: MilliAmperes(0) });
+        let values = bq769x0.compute_config(&test_config()).unwrap();
+        assert_eq!(values.ocdscd_range_used, OCDSCDRange::Upper);
+        assert_eq!(values.scd_threshold, Amperes(199));
+        assert_eq!(values.ocd_threshold, Amperes(100));
+        assert!(values.ov_threshold <= MilliVolts(4175) && values.ov_threshold > MilliVolts(4175 - 7));
+        assert!(values.uv_threshold >= MilliVolts(2000) && values.uv_threshold < MilliVolts(2000 + 7));
+        assert!(!bq769x0.is_initialized());
+
+        let config = Config::builder().ov_threshold(MilliVolts(5000)).build();
+        assert!(matches!(bq769x0.compute_config(&config), Err(Error::OVThresholdUnobtainable(_, _))));
+        let config = Config::builder().scd(Amperes(200), SCDDelay::_400uS).ocd(Amperes(10), OCDDelay::_320ms).build();
+        assert!(matches!(bq769x0.compute_config(&config), Err(Error::OCDSCDRangeMismatch(_))));
+
+        // init programs exactly what compute_config predicts
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.read_adc_characteristics(&mut i2c).unwrap();
+        let predicted = bq769x0.compute_config(&test_config()).unwrap();
+        assert_eq!(bq769x0.init(&mut i2c, &test_config()).unwrap(), predicted);
+    }
+
+    #[test]
+    fn trip_thresholds_at_range_extremes() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.read_adc_characteristics(&mut i2c).unwrap();
+        let (ov_min, ov_max) = bq769x0.ov_voltage_range();
+        let (uv_min, uv_max) = bq769x0.uv_voltage_range();
+
+        let mut config = test_config();
+        config.ov_threshold = ov_max;
+        config.uv_threshold = uv_min;
+        bq769x0.init(&mut i2c, &config).unwrap();
+        assert_eq!((i2c.regs[0x09], i2c.regs[0x0a]), (0xff, 0x00));
+
+        config.ov_threshold = ov_min;
+        config.uv_threshold = uv_max;
+        bq769x0.init(&mut i2c, &config).unwrap();
+        assert_eq!((i2c.regs[0x09], i2c.regs[0x0a]), (0x00, 0xff));
+
+        for &ov in [ov_max + MilliVolts(1), ov_min - MilliVolts(1), MilliVolts(0), MilliVolts(u32::MAX)].iter() {
+            config.ov_threshold = ov;
+            config.uv_threshold = uv_min;
+            assert!(matches!(bq769x0.init(&mut i2c, &config), Err(Error::OVThresholdUnobtainable(_, _))));
+        }
+        config.ov_threshold = ov_max;
+        for &uv in [uv_max + MilliVolts(1), uv_min - MilliVolts(1), MilliVolts(0), MilliVolts(u32::MAX)].iter() {
+            config.uv_threshold = uv;
+            assert!(matches!(bq769x0.init(&mut i2c, &config), Err(Error::UVThresholdUnobtainable(_, _))));
+        }
+        assert_eq!((i2c.regs[0x09], i2c.regs[0x0a]), (0x00, 0xff));
+
+        // OV never above, UV never below the requested threshold, and within one step (~6mV) of it
+        for mv in (ov_min.0..=ov_max.0).step_by(7) {
+            let (_, actual) = bq769x0.ov_trip(MilliVolts(mv)).unwrap();
+            assert!(actual <= MilliVolts(mv) && actual > MilliVolts(mv - 7), "OV {} -> {}", mv, actual);
+        }
+        for mv in (uv_min.0..=uv_max.0).step_by(7) {
+            let (_, actual) = bq769x0.uv_trip(MilliVolts(mv)).unwrap();
+            assert!(actual >= MilliVolts(mv) && actual < MilliVolts(mv + 7), "UV {} -> {}", mv, actual);
+        }
+    }
+
+    #[test]
+    fn regout_follows_ship_mode() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert!(bq769x0.is_regout_enabled(&mut i2c).unwrap());
+        assert_eq!(i2c.writes, 0);
+
+        i2c.nack = true; // device in SHIP mode
+        assert!(!bq769x0.is_regout_enabled(&mut i2c).unwrap());
+
+        // the device answered, but garbled: not a sign of SHIP mode
+        let mut bad = crc_block(&[(0x08 << 1) | 1], &[0x18]);
+        bad[1] ^= 0x01;
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, true).unwrap();
+        i2c.scripted_reads.push_back((0x04, bad));
+        assert!(matches!(bq769x0.is_regout_enabled(&mut i2c), Err(Error::CRCMismatch)));
+    }
+
+    #[test]
+    fn adc_characteristics_struct() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert!(matches!(bq769x0.adc_characteristics(), Err(Error::Uninitialized)));
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        let adc = bq769x0.adc_characteristics().unwrap();
+        assert_eq!(adc, AdcCharacteristics { gain_uv_per_lsb: bq769x0.adc_gain(), offset_mv: bq769x0.adc_offset() });
+        assert_eq!(adc, AdcCharacteristics { gain_uv_per_lsb: 378, offset_mv: 43 });
+    }
+
+    #[test]
+    fn settle_delay_setting() {
+        use crate::*;
+
+        let mut bus = SettleDelay::new(DummyI2C::new(), DummyDelay { total_ms: 0 });
+        assert_eq!(bus.delay_us(), DEFAULT_SETTLE_US);
+        bus.set_delay_us(2000);
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.write_raw(&mut bus, 0x01, &[0b1_0101]).unwrap();
+        assert_eq!(bq769x0.read_u8(&mut bus, 0x01).unwrap(), 0b1_0101);
+        let (i2c, delay) = bus.release();
+        assert_eq!(i2c.regs[0x01], 0b1_0101);
+        assert_eq!(delay.total_ms, 4); // one wait per transaction
+    }
+
+    #[test]
+    fn validate_cells_flags_stuck_readings() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 4, false).unwrap();
+        assert!(matches!(bq769x0.validate_cells(MilliVolts(1000), MilliVolts(5000)), Err(Error::Uninitialized)));
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        bq769x0.adc_gain = 1000;
+        bq769x0.adc_offset = 0;
+        // VC2 open (0mV), VC5 at the rail, VC4 unused and not checked
+        for (i, mv) in [3700u16, 0, 3710, 0, 0x3fff].iter().enumerate() {
+            i2c.regs[0x0c + i * 2] = (mv >> 8) as u8;
+            i2c.regs[0x0c + i * 2 + 1] = (mv & 0xff) as u8;
+        }
+        bq769x0.cell_voltages(&mut i2c).unwrap();
+        let bad = bq769x0.validate_cells(MilliVolts(1000), MilliVolts(5000)).unwrap();
+        assert_eq!(bad, CellMask(0b1_0010));
+        assert_eq!(bad.iter().collect::<std::vec::Vec<usize>>(), [1, 4]);
+        assert!(bq769x0.validate_cells(MilliVolts(0), MilliVolts(20000)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn poll_faults_reports_edges() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        i2c.scripted_reads.push_back((0x00, std::vec![(SysStat::OVERVOLTAGE | SysStat::CC_READY).bits()]));
+        assert_eq!(bq769x0.poll_faults(&mut i2c).unwrap(), SysStat::OVERVOLTAGE);
+
+        i2c.scripted_reads.push_back((0x00, std::vec![(SysStat::OVERVOLTAGE | SysStat::SHORTCIRCUIT).bits()]));
+        assert_eq!(bq769x0.poll_faults(&mut i2c).unwrap(), SysStat::SHORTCIRCUIT);
+        assert_eq!(bq769x0.poll_faults(&mut i2c).unwrap(), SysStat::empty());
+        assert_eq!(bq769x0.sys_stat(&mut i2c).unwrap(), SysStat::OVERVOLTAGE | SysStat::SHORTCIRCUIT);
+
+        bq769x0.clear_fault(&mut i2c, SysStat::OVERVOLTAGE).unwrap();
+        assert_eq!(bq769x0.poll_faults(&mut i2c).unwrap(), SysStat::empty());
+        i2c.scripted_reads.push_back((0x00, std::vec![(SysStat::OVERVOLTAGE | SysStat::SHORTCIRCUIT).bits()]));
+        assert_eq!(bq769x0.poll_faults(&mut i2c).unwrap(), SysStat::OVERVOLTAGE);
+    }
+
+    #[test]
+    fn fet_state_readback() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert_eq!(bq769x0.fet_state(&mut i2c).unwrap(), FetState { charge: false, discharge: false });
+
+        bq769x0.discharge(&mut i2c, true).unwrap();
+        assert!(bq769x0.is_discharge_enabled(&mut i2c).unwrap());
+        assert!(!bq769x0.is_charge_enabled(&mut i2c).unwrap());
+        assert_eq!(bq769x0.fet_state(&mut i2c).unwrap(), FetState { charge: false, discharge: true });
+
+        bq769x0.charge(&mut i2c, true).unwrap();
+        bq769x0.discharge(&mut i2c, false).unwrap();
+        assert!(!bq769x0.is_discharge_enabled(&mut i2c).unwrap());
+        assert!(bq769x0.is_charge_enabled(&mut i2c).unwrap());
+        assert_eq!(bq769x0.fet_state(&mut i2c).unwrap(), FetState { charge: true, discharge: false });
+    }
+
+    #[test]
+    fn ov_uv_ranges() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert!(matches!(bq769x0.ov_range(), Err(Error::Uninitialized)));
+        assert!(matches!(bq769x0.uv_range(), Err(Error::Uninitialized)));
+        bq769x0.read_adc_characteristics(&mut i2c).unwrap(); // 378uV/LSB, 43mV
+        assert_eq!(bq769x0.ov_range().unwrap(), (MilliVolts(3142), MilliVolts(4684)));
+        assert_eq!(bq769x0.uv_range().unwrap(), (MilliVolts(1591), MilliVolts(3133)));
+
+        let mut config = test_config();
+        config.ov_threshold = MilliVolts(4685);
+        assert!(matches!(bq769x0.init(&mut i2c, &config), Err(Error::OVThresholdUnobtainable(MilliVolts(3142), MilliVolts(4684)))));
+    }
+
+    #[test]
+    fn xready_recovery() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut delay = DummyDelay { total_ms: 0 };
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.recover_xready(&mut i2c, &mut delay).unwrap();
+        assert_eq!(delay.total_ms, 0);
+
+        i2c.regs[0x00] = (SysStat::DEVICE_XREADY | SysStat::OVERCURRENT).bits();
+        bq769x0.recover_xready(&mut i2c, &mut delay).unwrap();
+        assert_eq!(delay.total_ms, XREADY_RECOVERY_MS as u32);
+        assert_eq!(i2c.regs[0x00], SysStat::OVERCURRENT.bits());
+
+        // flag comes straight back after clearing
+        let xready = std::vec![SysStat::DEVICE_XREADY.bits()];
+        i2c.scripted_reads.push_back((0x00, xready.clone()));
+        i2c.scripted_reads.push_back((0x00, xready));
+        assert!(matches!(bq769x0.recover_xready(&mut i2c, &mut delay), Err(Error::XReadyPersistent)));
+    }
+
+    #[test]
+    fn single_cell_voltage() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq76930 = BQ769x0::<BQ76930>::new(0x08, 9, false).unwrap();
+        assert!(matches!(bq76930.cell_voltage(&mut i2c, 0), Err(Error::Uninitialized)));
+        bq76930.init(&mut i2c, &test_config()).unwrap();
+        bq76930.adc_gain = 1000;
+        bq76930.adc_offset = 0;
+        i2c.regs[0x0c + 7 * 2] = 0x0e;
+        i2c.regs[0x0c + 7 * 2 + 1] = 0x74;
+        i2c.reads = 0;
+        assert_eq!(bq76930.cell_voltage(&mut i2c, 7).unwrap(), MilliVolts(3700));
+        assert_eq!(i2c.reads, 1);
+
+        assert!(matches!(bq76930.cell_voltage(&mut i2c, 8), Err(Error::InvalidCell(8)))); // VC9 unused with 5+4 cells
+        assert!(matches!(bq76930.cell_voltage(&mut i2c, 10), Err(Error::InvalidCell(10))));
+        assert_eq!(i2c.reads, 1);
+    }
+
+    #[test]
+    fn deci_celsius_conversions() {
+        use crate::*;
+
+        assert_eq!(DeciCelsius::from(DegreesCentigrade(45)), DeciCelsius(450));
+        assert_eq!(DeciCelsius::from(DegreesCentigrade(-20)), DeciCelsius(-200));
+        assert_eq!(DeciCelsius(449).to_degrees(), DegreesCentigrade(45));
+        assert_eq!(DeciCelsius(444).to_degrees(), DegreesCentigrade(44));
+        assert_eq!(DeciCelsius(-15).to_degrees(), DegreesCentigrade(-2));
+        assert_eq!(DeciCelsius(-14).to_degrees(), DegreesCentigrade(-1));
+        assert!(DeciCelsius(451) > DegreesCentigrade(45).into());
+        assert_eq!(std::format!("{}", DeciCelsius(451)), "45.1degC");
+        assert_eq!(std::format!("{}", DeciCelsius(-5)), "-0.5degC");
+        assert_eq!(std::format!("{}", DeciCelsius(-105)), "-10.5degC");
+    }
+
+    #[test]
+    fn mock_discharge_read_modify_write() {
+        use crate::*;
+        use crate::mock::{I2cMock, Transaction};
+
+        let mut i2c = I2cMock::new(&[
+            Transaction::read(0x08, regs::SYS_CTRL2, &[0b0100_0001]),
+            Transaction::write(0x08, regs::SYS_CTRL2, &[0b0100_0011]),
+            Transaction::read(0x08, regs::SYS_CTRL2, &[0b0100_0011]),
+        ]);
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert!(bq769x0.discharge(&mut i2c, true).is_ok());
+        // already enabled, no write
+        assert!(bq769x0.discharge(&mut i2c, true).is_ok());
+        i2c.done();
+    }
+
+    #[test]
+    fn mock_nack_is_i2c_error() {
+        use crate::*;
+        use crate::mock::{I2cMock, Transaction};
+
+        let mut i2c = I2cMock::new(&[Transaction::write(0x08, regs::SYS_STAT, &[SysStat::OVRD_ALERT.bits()]).with_nack()]);
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert!(matches!(bq769x0.clear_fault(&mut i2c, SysStat::OVRD_ALERT), Err(Error::I2CError)));
+        i2c.done();
+    }
+
+    #[test]
+    #[should_panic(expected = "transaction mismatch")]
+    fn mock_rejects_unexpected_register() {
+        use crate::*;
+        use crate::mock::{I2cMock, Transaction};
+
+        let mut i2c = I2cMock::new(&[Transaction::read(0x08, regs::SYS_CTRL1, &[0])]);
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        let _ = bq769x0.sys_stat(&mut i2c);
+    }
+
+    #[test]
+    fn balance_session_rotates_adjacent_cells() {
+        use crate::*;
+
+        let mut cells = [MilliVolts(3585); 10];
+        cells[0] = MilliVolts(3700);
+        cells[1] = MilliVolts(3690);
+        cells[2] = MilliVolts(3680);
+        cells[4] = MilliVolts(3580);
+        cells[5] = MilliVolts(3650);
+        cells[6] = MilliVolts(3605);
+        let mut session = BalanceSession::new(CellMask(0b11_1111_1111), MilliVolts(10));
+        let iter = |cells: &[MilliVolts; 10]| cells.iter().copied().enumerate().collect::<std::vec::Vec<_>>();
+
+        // cells 0, 1, 2, 5 and 6 are above 3580 + 10
+        assert_eq!(session.next_mask(iter(&cells)), CellMask(0b00_0010_0101));
+        assert_eq!(session.next_mask(iter(&cells)), CellMask(0b00_0100_0010));
+        assert!(!session.is_done());
+        let mut steps = 0;
+        loop {
+            let mask = session.next_mask(iter(&cells));
+            if session.is_done() {
+                assert!(mask.is_empty());
+                break;
+            }
+            assert!(!BQ769x0::<BQ76930>::has_adjacent_cells(mask.bits()));
+            for cell in mask.iter() {
+                cells[cell] = cells[cell] - MilliVolts(5);
+            }
+            steps += 1;
+            assert!(steps < 100);
+        }
+        // the lowest cell is never balanced
+        assert_eq!(cells[4], MilliVolts(3580));
+        assert!(cells.iter().all(|&mv| mv <= MilliVolts(3590)));
+    }
+
+    #[test]
+    fn balance_session_only_balances_targets() {
+        use crate::*;
+
+        let cells = [(0, MilliVolts(3700)), (1, MilliVolts(3500)), (2, MilliVolts(3700)), (3, MilliVolts(3700))];
+        let mut session = BalanceSession::new(CellMask(0b1000), MilliVolts(20));
+        assert_eq!(session.next_mask(cells.iter().copied()), CellMask(0b1000));
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert!(matches!(session.step(&mut bq769x0, &mut i2c), Err(Error::Uninitialized)));
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        for (cell, mv) in [3700u16, 3500, 3600, 3700, 3700].iter().enumerate() {
+            let raw = ((*mv as u32 * 1000 - 43_000) / 378) as u16;
+            i2c.regs[0x0c + cell * 2..0x0e + cell * 2].copy_from_slice(&raw.to_be_bytes());
+        }
+        bq769x0.cell_voltages(&mut i2c).unwrap();
+        let mut session = BalanceSession::new(CellMask(0b1_1111), MilliVolts(20));
+        assert_eq!(session.step(&mut bq769x0, &mut i2c).unwrap(), CellMask(0b1_0101));
+        assert_eq!(i2c.regs[0x01], 0b1_0101);
+        assert_eq!(session.step(&mut bq769x0, &mut i2c).unwrap(), CellMask(0b0_1001));
+        assert_eq!(i2c.regs[0x01], 0b0_1001);
+    }
+
+    const _: () = assert!(crate::BQ769x0::<{ crate::BQ76920 }>::MAX_CELLS == 5);
+    const _: () = assert!(crate::BQ769x0::<{ crate::BQ76930 }>::MAX_CELLS == 10);
+    const _: () = assert!(crate::BQ769x0::<{ crate::BQ76940 }>::MAX_CELLS == 15);
+    const _: () = assert!(crate::BQ769x0::<{ crate::BQ76920 }>::TS_INPUTS == 1);
+    const _: () = assert!(crate::BQ769x0::<{ crate::BQ76930 }>::TS_INPUTS == 2);
+    const _: () = assert!(crate::BQ769x0::<{ crate::BQ76940 }>::TS_INPUTS == 3);
+
+    #[test]
+    fn configured_cell_count() {
+        use crate::*;
+
+        fn buffer_len<const X: usize>(_: &BQ769x0<X>) -> usize {
+            BQ769x0::<X>::MAX_CELLS
+        }
+        let bq769x0 = BQ769x0::<BQ76940>::new(0x08, 12, false).unwrap();
+        assert_eq!(bq769x0.cell_count(), 12);
+        assert_eq!(buffer_len(&bq769x0), 15);
+    }
+
+    #[test]
+    fn register_dump() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        for reg in 0..REGISTER_DUMP_LEN {
+            i2c.regs[reg] = reg as u8;
+        }
+        i2c.regs[regs::SYS_STAT as usize] = 0b1000_0011;
+        i2c.regs[regs::SYS_CTRL2 as usize] = 0b0100_0011;
+        i2c.regs[regs::CC_HI as usize..regs::CC_LO as usize + 1].copy_from_slice(&(-100i16).to_be_bytes());
+        let mut bq769x0 = BQ769x0::<BQ76930>::new(0x08, 10, false).unwrap();
+        let mut dump = [0u8; REGISTER_DUMP_LEN];
+        bq769x0.dump_registers(&mut i2c, &mut dump).unwrap();
+        assert_eq!(&dump[..], &i2c.regs[..REGISTER_DUMP_LEN]);
+        assert_eq!(i2c.reads, 2);
+
+        let text = std::format!("{}", RegisterDump(&dump));
+        assert!(text.contains("SYS_STAT  0x83 CC_READY | SHORTCIRCUIT | OVERCURRENT"));
+        assert!(text.contains("SYS_CTRL2 0x43 CC_EN | DSG_ON | CHG_ON"));
+        assert!(text.contains(&std::format!("VC1       {}", 0x0c0d)));
+        assert!(text.ends_with("CC        -100"));
+    }
+
+    #[test]
+    fn detect_custom_address() {
+        use crate::*;
+        use crate::mock::{I2cMock, Transaction};
+
+        // nothing answers on 0x18, with or without CRC
+        let mut i2c = I2cMock::new(&[
+            Transaction::write(0x18, regs::UV_TRIP, &[0xaa]).with_nack(),
+            Transaction::write(0x18, regs::UV_TRIP, &crc_block(&[0x18 << 1, regs::UV_TRIP], &[0xaa])).with_nack(),
+            Transaction::write(0x28, regs::UV_TRIP, &[0xaa]),
+            Transaction::read(0x28, regs::UV_TRIP, &[0xaa]),
+        ]);
+        let bq769x0 = BQ769x0::<BQ76920>::new_detect_at(&mut i2c, 5, &[0x18, 0x28]).unwrap();
+        assert_eq!(bq769x0.i2c_address(), 0x28);
+        assert!(!bq769x0.is_crc_used());
+        i2c.done();
+
+        let mut i2c = I2cMock::new(&[
+            Transaction::write(0x28, regs::UV_TRIP, &[0xaa]).with_nack(),
+            Transaction::write(0x28, regs::UV_TRIP, &crc_block(&[0x28 << 1, regs::UV_TRIP], &[0xaa])).with_nack(),
+        ]);
+        assert!(BQ769x0::<BQ76920>::new_detect_at(&mut i2c, 5, &[0x28]).is_none());
+        i2c.done();
+    }
+
+    #[test]
+    fn averaged_cell_voltages() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        let mv = |raw: u16| (raw as u32 * 378 + 43_000) / 1000;
+        for jitter in [0u16, 7, 2, 11].iter() {
+            let mut block = std::vec::Vec::new();
+            for cell in 0..5u16 {
+                block.extend_from_slice(&(9600 + cell * 10 + jitter).to_be_bytes());
+            }
+            i2c.scripted_reads.push_back((0x0c, block));
+        }
+        let cells = bq769x0.cell_voltages_averaged(&mut i2c, 4).unwrap().to_vec();
+        for (cell, avg) in cells.iter().enumerate() {
+            let sum: u32 = [0u16, 7, 2, 11].iter().map(|j| mv(9600 + cell as u16 * 10 + j)).sum();
+            assert_eq!(*avg, MilliVolts(sum / 4));
+        }
+        assert_eq!(bq769x0.min_cell().unwrap().1, cells[0]);
+        assert!(i2c.scripted_reads.is_empty());
+    }
+
+    #[test]
+    fn telemetry_before_init() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76930>::new(0x08, 10, false).unwrap();
+        // calibration alone is not enough, the protection registers were never programmed
+        bq769x0.import_calibration(&Calibration { adc_gain: 378, adc_offset: 43, shunt: MicroOhms(667), current_offset: MilliAmperes(0) });
+        assert!(matches!(bq769x0.voltage(&mut i2c), Err(Error::Uninitialized)));
+        assert!(matches!(bq769x0.current(&mut i2c), Err(Error::Uninitialized)));
+        assert!(matches!(bq769x0.current_when_ready(&mut i2c, &mut DummyDelay { total_ms: 0 }, 0), Err(Error::Uninitialized)));
+        assert!(matches!(bq769x0.power(&mut i2c), Err(Error::Uninitialized)));
+        assert!(matches!(bq769x0.temperature(&mut i2c), Err(Error::Uninitialized)));
+        assert!(matches!(bq769x0.temperature_all(&mut i2c), Err(Error::Uninitialized)));
+        assert!(matches!(bq769x0.cell_voltages(&mut i2c), Err(Error::Uninitialized)));
+        assert!(matches!(bq769x0.read_telemetry(&mut i2c), Err(Error::Uninitialized)));
+        assert_eq!((i2c.reads, i2c.writes), (0, 0));
+
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        assert!(bq769x0.voltage(&mut i2c).is_ok());
+        assert!(bq769x0.temperature(&mut i2c).is_ok());
+        assert!(bq769x0.power(&mut i2c).is_ok());
+    }
+
+    #[test]
+    fn charge_accumulation() {
+        use crate::*;
+
+        let mut acc = ChargeAccumulator::new();
+        assert_eq!(acc.average_current(), MilliAmperes(0));
+        // 1h of 250ms samples: 2A charging for 30 minutes, then 1A discharging for 30 minutes
+        for sample in 0..14_400 {
+            acc.add(if sample < 7200 { MilliAmperes(2000) } else { MilliAmperes(-1000) });
+        }
+        assert_eq!(acc.charge(), MilliAmpereHours(500));
+        assert_eq!(acc.average_current(), MilliAmperes(500));
+        assert_eq!(acc.samples(), 14_400);
+        acc.reset();
+        acc.add(MilliAmperes(-3600));
+        assert_eq!(acc.charge(), MilliAmpereHours(0));
+        assert_eq!(acc.average_current(), MilliAmperes(-3600));
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        let mut acc = ChargeAccumulator::new();
+        for raw in [100i16, -50, -50].iter() {
+            i2c.regs[0x00] = SysStat::CC_READY.bits();
+            i2c.regs[0x32..0x34].copy_from_slice(&raw.to_be_bytes());
+            assert!(bq769x0.accumulate_charge(&mut i2c, &mut acc).unwrap());
+            assert_eq!(i2c.regs[0x00], 0);
+            // no new sample yet
+            assert!(!bq769x0.accumulate_charge(&mut i2c, &mut acc).unwrap());
+        }
+        assert_eq!(acc.samples(), 3);
+        let ma = |raw: i32| raw * 8440 / 667;
+        assert_eq!(acc.average_current(), MilliAmperes((ma(100) + 2 * ma(-50)) / 3));
+    }
+
+    #[test]
+    fn new_checked_crc_mode() {
+        use crate::*;
+        use crate::mock::{I2cMock, Transaction};
+
+        let write = crc_block(&[0x08 << 1, regs::UV_TRIP], &[0xaa]);
+        let read = crc_block(&[(0x08 << 1) | 1], &[0xaa]);
+        // device strapped for CRC, non-CRC write is rejected
+        let mut i2c = I2cMock::new(&[
+            Transaction::write(0x08, regs::UV_TRIP, &[0xaa]).with_nack(),
+            Transaction::write(0x08, regs::UV_TRIP, &write),
+            Transaction::read(0x08, regs::UV_TRIP, &read),
+        ]);
+        assert!(matches!(BQ769x0::<BQ76920>::new_checked(&mut i2c, 0x08, 5, false), Err(Error::CRCModeMismatch(true))));
+        i2c.done();
+
+        let mut i2c = I2cMock::new(&[
+            Transaction::write(0x08, regs::UV_TRIP, &write),
+            Transaction::read(0x08, regs::UV_TRIP, &read),
+        ]);
+        let bq769x0 = BQ769x0::<BQ76920>::new_checked(&mut i2c, 0x08, 5, true).unwrap();
+        assert!(bq769x0.is_crc_used());
+        i2c.done();
+
+        let mut i2c = I2cMock::new(&[]);
+        assert!(matches!(BQ769x0::<BQ76920>::new_checked(&mut i2c, 0x08, 6, false), Err(Error::InvalidCellCount(6))));
+        let mut i2c = I2cMock::new(&[
+            Transaction::write(0x08, regs::UV_TRIP, &[0xaa]).with_nack(),
+            Transaction::write(0x08, regs::UV_TRIP, &write).with_nack(),
+        ]);
+        assert!(matches!(BQ769x0::<BQ76920>::new_checked(&mut i2c, 0x08, 5, false), Err(Error::I2CError)));
+        i2c.done();
+    }
+
+    #[test]
+    fn shunt_update_after_init() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        assert_eq!(bq769x0.shunt(), MicroOhms(667));
+        i2c.regs[0x32..0x34].copy_from_slice(&1000i16.to_be_bytes());
+        assert_eq!(bq769x0.current(&mut i2c).unwrap(), MilliAmperes(1000 * 8440 / 667));
+        let protect1 = i2c.regs[0x06];
+
+        bq769x0.set_shunt(MicroOhms(700)).unwrap();
+        assert_eq!(bq769x0.shunt(), MicroOhms(700));
+        assert_eq!(bq769x0.current(&mut i2c).unwrap(), MilliAmperes(1000 * 8440 / 700));
+        // thresholds are left as programmed
+        assert_eq!(i2c.regs[0x06], protect1);
+
+        assert!(matches!(bq769x0.set_shunt(MicroOhms(0)), Err(Error::ZeroShunt)));
+        assert_eq!(bq769x0.shunt(), MicroOhms(700));
+    }
+
+    #[test]
+    fn calculated_values_to_config() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        let values = bq769x0.init(&mut i2c, &test_config()).unwrap();
+        let golden = i2c.regs[0x06..0x0c].to_vec();
+
+        let config = values.to_config();
+        assert_eq!(config.scd_threshold, values.scd_threshold);
+        assert_eq!(config.ov_threshold, values.ov_threshold);
+        assert_eq!(config.ocd_delay, OCDDelay::_1280ms);
+        assert_eq!(config.cc_cfg, CC_CFG_RECOMMENDED);
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert_eq!(bq769x0.init(&mut i2c, &config).unwrap(), values);
+        assert_eq!(&i2c.regs[0x06..0x0c], &golden[..]);
+    }
+
+    #[test]
+    fn sys_ctrl_flag_bit_positions() {
+        use crate::*;
+
+        assert_eq!(SysCtrl1::LOAD_PRESENT.bits(), 1 << 7);
+        assert_eq!(SysCtrl1::ADC_EN.bits(), 1 << 4);
+        assert_eq!(SysCtrl1::TEMP_SEL.bits(), 1 << 3);
+        assert_eq!(SysCtrl1::SHUT_A.bits(), 1 << 1);
+        assert_eq!(SysCtrl1::SHUT_B.bits(), 1 << 0);
+        assert_eq!(SysCtrl2::DELAY_DIS.bits(), 1 << 7);
+        assert_eq!(SysCtrl2::CC_EN.bits(), 1 << 6);
+        assert_eq!(SysCtrl2::CC_ONESHOT.bits(), 1 << 5);
+        assert_eq!(SysCtrl2::DSG_ON.bits(), 1 << 1);
+        assert_eq!(SysCtrl2::CHG_ON.bits(), 1 << 0);
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.charge(&mut i2c, true).unwrap();
+        assert_eq!(i2c.regs[0x05], 0b0000_0001);
+        bq769x0.discharge(&mut i2c, true).unwrap();
+        assert_eq!(i2c.regs[0x05], 0b0000_0011);
+        bq769x0.coulomb_counter_mode(&mut i2c, CoulombCounterMode::OneShot).unwrap();
+        assert_eq!(i2c.regs[0x05], 0b0010_0011);
+        bq769x0.coulomb_counter_mode(&mut i2c, CoulombCounterMode::Continuous).unwrap();
+        assert_eq!(i2c.regs[0x05], 0b0100_0011);
+        bq769x0.charge(&mut i2c, false).unwrap();
+        assert_eq!(i2c.regs[0x05], 0b0100_0010);
+        bq769x0.coulomb_counter_mode(&mut i2c, CoulombCounterMode::Disabled).unwrap();
+        assert_eq!(i2c.regs[0x05], 0b0000_0010);
+        bq769x0.enable_coulomb_counter(&mut i2c).unwrap();
+        assert_eq!(i2c.regs[0x05], 0b0100_0010);
+
+        bq769x0.enable_adc(&mut i2c, true).unwrap();
+        assert_eq!(i2c.regs[0x04], 0b0001_0000);
+        bq769x0.set_temperature_source(&mut i2c, TemperatureSource::ExternalThermistor).unwrap();
+        assert_eq!(i2c.regs[0x04], 0b0001_1000);
+        bq769x0.enable_adc(&mut i2c, false).unwrap();
+        assert_eq!(i2c.regs[0x04], 0b0000_1000);
+    }
+
+    #[test]
+    fn try_new_reports_reason() {
+        use crate::*;
+
+        assert!(BQ769x0::<BQ76920>::try_new(0x08, 3, false).is_ok());
+        assert!(BQ769x0::<BQ76940>::try_new(0x08, 15, true).is_ok());
+        assert!(matches!(BQ769x0::<BQ76920>::try_new(0x08, 2, false), Err(Error::InvalidCellCount(2))));
+        assert!(matches!(BQ769x0::<BQ76930>::try_new(0x08, 11, false), Err(Error::InvalidCellCount(11))));
+        assert!(matches!(BQ769x0::<BQ76940>::try_new(0x08, 8, false), Err(Error::InvalidCellCount(8))));
+        assert!(matches!(BQ769x0::<7>::try_new(0x08, 5, false), Err(Error::InvalidVariant(7))));
+        assert!(BQ769x0::<7>::new(0x08, 5, false).is_none());
+        assert!(BQ769x0::<BQ76930>::new(0x08, 5, false).is_none());
+    }
+
+    #[test]
+    fn ocv_soc_interpolation() {
+        use crate::*;
+
+        let lfp = [(MilliVolts(2800), 0), (MilliVolts(3200), 10), (MilliVolts(3300), 50), (MilliVolts(3350), 90), (MilliVolts(3600), 100)];
+        assert_eq!(ocv_to_soc(&lfp, MilliVolts(2500)), 0);
+        assert_eq!(ocv_to_soc(&lfp, MilliVolts(2800)), 0);
+        assert_eq!(ocv_to_soc(&lfp, MilliVolts(3000)), 5);
+        assert_eq!(ocv_to_soc(&lfp, MilliVolts(3200)), 10);
+        assert_eq!(ocv_to_soc(&lfp, MilliVolts(3275)), 40);
+        assert_eq!(ocv_to_soc(&lfp, MilliVolts(3325)), 70);
+        assert_eq!(ocv_to_soc(&lfp, MilliVolts(3600)), 100);
+        assert_eq!(ocv_to_soc(&lfp, MilliVolts(3700)), 100);
+        assert_eq!(ocv_to_soc(&[], MilliVolts(3700)), 0);
+        assert_eq!(ocv_to_soc(&[(MilliVolts(3700), 60)], MilliVolts(3000)), 60);
+
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 4, false).unwrap();
+        assert!(matches!(bq769x0.cells_soc(&lfp), Err(Error::Uninitialized)));
+        bq769x0.cells = [MilliVolts(3000), MilliVolts(3300), MilliVolts(3475), MilliVolts(2000), MilliVolts(3300)];
+        bq769x0.cells_read = true;
+        assert_eq!(bq769x0.cells_soc(&lfp).unwrap(), [5, 50, 95, 0, 0]);
+    }
+
+    #[test]
+    fn current_threshold_over_range() {
+        use crate::*;
+
+        // 2mOhm: 200mV SCD = 100A, 100mV OCD = 50A
+        assert!(matches!(SCDThreshold::from_current_checked(Amperes(100), MicroOhms(2000)), Ok(SCDThreshold::_200mV)));
+        assert!(matches!(SCDThreshold::from_current_checked(Amperes(101), MicroOhms(2000)), Err(Error::SCDThresholdUnobtainable(Amperes(100)))));
+        assert!(matches!(SCDThreshold::from_current_checked(Amperes(300), MicroOhms(2000)), Err(Error::SCDThresholdUnobtainable(Amperes(100)))));
+        assert!(matches!(OCDThreshold::from_current_checked(Amperes(50), MicroOhms(2000)), Ok(OCDThreshold::_100mV)));
+        assert!(matches!(OCDThreshold::from_current_checked(Amperes(51), MicroOhms(2000)), Err(Error::OCDThresholdUnobtainable(Amperes(50)))));
+        // 300A * 2mOhm = 600mV used to wrap around to a 88mV step
+        assert!(matches!(SCDThreshold::from_current(Amperes(300), MicroOhms(2000)), SCDThreshold::_200mV));
+        assert_eq!(SCDThreshold::from_current_actual(Amperes(300), MicroOhms(2000)).1, Amperes(100));
+
+        let config = Config::builder().shunt(MicroOhms(2000)).scd(Amperes(300), SCDDelay::_400uS).ocd(Amperes(40), OCDDelay::_320ms).build();
+        assert!(matches!(config.check_ocdscd_range(), Err(Error::SCDThresholdUnobtainable(Amperes(100)))));
+        let config = Config::builder().shunt(MicroOhms(2000)).scd(Amperes(90), SCDDelay::_400uS).ocd(Amperes(60), OCDDelay::_320ms).build();
+        assert!(matches!(config.check_ocdscd_range(), Err(Error::OCDThresholdUnobtainable(Amperes(50)))));
+    }
+
+    #[test]
+    fn protection_outputs_refuse_active_fault() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert!(matches!(bq769x0.enable_protection_outputs(&mut i2c), Err(Error::Uninitialized)));
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+
+        i2c.regs[0x00] = (SysStat::CC_READY | SysStat::UNDERVOLTAGE).bits();
+        match bq769x0.enable_protection_outputs(&mut i2c) {
+            Err(Error::ActiveFaults(faults)) => assert_eq!(faults, SysStat::UNDERVOLTAGE),
+            _ => panic!("expected ActiveFaults"),
+        }
+        assert_eq!(i2c.regs[0x05] & 0b11, 0);
+
+        // CC_READY is not a fault
+        i2c.regs[0x00] = SysStat::CC_READY.bits();
+        let writes = i2c.writes;
+        bq769x0.enable_protection_outputs(&mut i2c).unwrap();
+        assert_eq!(i2c.writes, writes + 1);
+        assert_eq!(i2c.regs[0x05], (SysCtrl2::CC_EN | SysCtrl2::CHG_ON | SysCtrl2::DSG_ON).bits());
+    }
+
+    #[test]
+    fn physical_vs_mapped_cell_voltages() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76930>::new(0x08, 8, false).unwrap();
+        assert!(matches!(bq769x0.cell_voltages_physical(&mut i2c), Err(Error::Uninitialized)));
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        for channel in 0..10usize {
+            // unused VC4 and VC9 read as shorted inputs
+            let raw: u16 = if channel == 3 || channel == 8 { 0 } else { 9000 + channel as u16 * 100 };
+            i2c.regs[0x0c + channel * 2..0x0e + channel * 2].copy_from_slice(&raw.to_be_bytes());
+        }
+        let physical = bq769x0.cell_voltages_physical(&mut i2c).unwrap();
+        assert!(bq769x0.cells_iter().next().is_none());
+        assert_eq!(physical[3], MilliVolts(43));
+        assert_eq!(physical[8], MilliVolts(43));
+        assert_eq!(physical[9], MilliVolts((9900 * 378 + 43_000) / 1000));
+
+        let mapped = bq769x0.cell_voltages(&mut i2c).unwrap().to_vec();
+        assert_eq!(mapped.len(), 8);
+        let connected: std::vec::Vec<MilliVolts> = bq769x0.connected_cell_map().iter().map(|n| physical[n]).collect();
+        assert_eq!(mapped, connected);
+        assert!(!mapped.contains(&MilliVolts(43)));
+    }
+
+    #[test]
+    fn raw_coulomb_counter() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        i2c.regs[0x32] = 0xff;
+        i2c.regs[0x33] = 0x38;
+        assert_eq!(bq769x0.coulomb_counter_raw(&mut i2c).unwrap(), -200);
+        i2c.regs[0x32] = 0x01;
+        i2c.regs[0x33] = 0x2c;
+        assert_eq!(bq769x0.coulomb_counter_raw(&mut i2c).unwrap(), 300);
+
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        bq769x0.set_current_polarity(CurrentPolarity::DischargePositive);
+        bq769x0.set_current_offset(MilliAmperes(10));
+        assert_eq!(bq769x0.coulomb_counter_raw(&mut i2c).unwrap(), 300);
+        // offset is in the CC's sign, removed before the polarity flip
+        assert_eq!(bq769x0.current(&mut i2c).unwrap(), MilliAmperes(-(300 * 8440 / 667 - 10)));
+    }
+
+    #[test]
+    fn trimmed_cc_lsb() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        assert_eq!(bq769x0.cc_lsb(), CC_LSB_NV);
+        i2c.regs[0x32..0x34].copy_from_slice(&10_000i16.to_be_bytes());
+        assert_eq!(bq769x0.current(&mut i2c).unwrap(), MilliAmperes(10_000 * 8440 / 667));
+
+        // +1%
+        bq769x0.set_cc_lsb(8524);
+        assert_eq!(bq769x0.cc_lsb(), 8524);
+        assert_eq!(bq769x0.current(&mut i2c).unwrap(), MilliAmperes(10_000 * 8524 / 667));
+        // full scale does not overflow
+        i2c.regs[0x32..0x34].copy_from_slice(&i16::MIN.to_be_bytes());
+        bq769x0.set_cc_lsb(100_000);
+        assert_eq!(bq769x0.current(&mut i2c).unwrap(), MilliAmperes((-32768i64 * 100_000 / 667) as i32));
+    }
+
+    #[test]
+    fn balancing_watchdog() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76930>::new(0x08, 10, false).unwrap();
+        assert!(!bq769x0.balance_tick(&mut i2c, 100_000).unwrap());
+
+        bq769x0.balance_with_timeout(&mut i2c, 0b00100_00101, 60_000).unwrap();
+        assert_eq!((i2c.regs[0x01], i2c.regs[0x02]), (0b0_0101, 0b0_0100));
+        assert!(!bq769x0.balance_tick(&mut i2c, 30_000).unwrap());
+        assert!(!bq769x0.balance_tick(&mut i2c, 29_999).unwrap());
+        assert_eq!(i2c.regs[0x01], 0b0_0101);
+        // refresh restarts the on-time
+        bq769x0.balance_with_timeout(&mut i2c, 0b00100_00101, 60_000).unwrap();
+        assert!(!bq769x0.balance_tick(&mut i2c, 59_000).unwrap());
+        assert!(bq769x0.balance_tick(&mut i2c, 1_000).unwrap());
+        assert_eq!((i2c.regs[0x01], i2c.regs[0x02]), (0, 0));
+        // stays off without a refresh
+        let writes = i2c.writes;
+        assert!(!bq769x0.balance_tick(&mut i2c, 60_000).unwrap());
+        assert_eq!(i2c.writes, writes);
+
+        bq769x0.balance_with_timeout(&mut i2c, 0b1, 1_000).unwrap();
+        i2c.nack = true;
+        assert!(matches!(bq769x0.balance_tick(&mut i2c, 1_000), Err(Error::I2CError)));
+        i2c.nack = false;
+        assert!(bq769x0.balance_tick(&mut i2c, 0).unwrap());
+        assert_eq!(i2c.regs[0x01], 0);
+    }
+
+    #[test]
+    fn probe_reports_crc_mode() {
+        use crate::*;
+        use crate::mock::{I2cMock, Transaction};
+
+        let mut i2c = DummyI2C::new();
+        assert_eq!(probe(&mut i2c, 0x08), Some(ProbeInfo { address: 0x08, use_crc: false }));
+        i2c.nack = true;
+        assert_eq!(probe(&mut i2c, 0x08), None);
+
+        let write = crc_block(&[0x18 << 1, regs::UV_TRIP], &[0xaa]);
+        let read = crc_block(&[(0x18 << 1) | 1], &[0xaa]);
+        let mut i2c = I2cMock::new(&[
+            Transaction::write(0x18, regs::UV_TRIP, &[0xaa]).with_nack(),
+            Transaction::write(0x18, regs::UV_TRIP, &write),
+            Transaction::read(0x18, regs::UV_TRIP, &read),
+        ]);
+        assert_eq!(probe(&mut i2c, 0x18), Some(ProbeInfo { address: 0x18, use_crc: true }));
+        i2c.done();
+    }
+
+    #[test]
+    fn write_block_splits_into_chunks() {
+        use crate::*;
+        use crate::mock::{I2cMock, Transaction};
+
+        let block: [u8; 12] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.write_block(&mut i2c, 0x20, &block).unwrap();
+        assert_eq!(i2c.writes, 2);
+        assert_eq!(&i2c.regs[0x20..0x2c], &block[..]);
+        assert!(matches!(bq769x0.write_block(&mut i2c, 0xf8, &block), Err(Error::BufTooLarge)));
+        assert_eq!(i2c.writes, 2);
+
+        // CRC restarts with the address at the start of each chunk
+        let first = crc_block(&[0x08 << 1, 0x20], &block[..8]);
+        let second = crc_block(&[0x08 << 1, 0x28], &block[8..]);
+        let mut i2c = I2cMock::new(&[
+            Transaction::write(0x08, 0x20, &first),
+            Transaction::write(0x08, 0x28, &second),
+        ]);
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, true).unwrap();
+        bq769x0.write_block(&mut i2c, 0x20, &block).unwrap();
+        i2c.done();
+    }
+
+    #[test]
+    fn temperature_source_reads_temp_sel() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        i2c.regs[0x04] = (SysCtrl1::ADC_EN | SysCtrl1::TEMP_SEL).bits();
+        assert_eq!(bq769x0.temperature_source(&mut i2c).unwrap(), TemperatureSource::ExternalThermistor);
+        i2c.regs[0x04] = SysCtrl1::ADC_EN.bits();
+        assert_eq!(bq769x0.temperature_source(&mut i2c).unwrap(), TemperatureSource::InternalDie);
+
+        // the first temperature read picks the source up from the device
+        i2c.regs[0x04] = SysCtrl1::TEMP_SEL.bits();
+        bq769x0.init_complete = true;
+        assert!(matches!(bq769x0.temperature(&mut i2c).unwrap(), Temperature::ExternalThermistor(_)));
+    }
+
+    #[test]
+    fn temperature_source_is_cached() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init_complete = true;
+        assert_eq!(bq769x0.known_temperature_source(), None);
+        bq769x0.temperature(&mut i2c).unwrap();
+        assert_eq!(bq769x0.known_temperature_source(), Some(TemperatureSource::InternalDie));
+
+        bq769x0.set_temperature_source(&mut i2c, TemperatureSource::ExternalThermistor).unwrap();
+        assert_eq!(bq769x0.known_temperature_source(), Some(TemperatureSource::ExternalThermistor));
+        let reads = i2c.reads;
+        assert!(matches!(bq769x0.temperature(&mut i2c).unwrap(), Temperature::ExternalThermistor(_)));
+        assert_eq!(i2c.reads, reads + 1);
+
+        bq769x0.ship_enter(&mut i2c).unwrap();
+        assert_eq!(bq769x0.known_temperature_source(), None);
+    }
+
+    #[test]
+    fn temperature_source_cache_invalidated() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        let mut delay = DummyDelay { total_ms: 0 };
+        bq769x0.set_temperature_source(&mut i2c, TemperatureSource::ExternalThermistor).unwrap();
+
+        // raw write to SYS_CTRL1, alone or as part of a block
+        bq769x0.write_raw(&mut i2c, regs::SYS_CTRL1, &[0]).unwrap();
+        assert_eq!(bq769x0.known_temperature_source(), None);
+        bq769x0.set_temperature_source(&mut i2c, TemperatureSource::ExternalThermistor).unwrap();
+        bq769x0.write_block(&mut i2c, regs::CELLBAL1, &[0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(bq769x0.known_temperature_source(), None);
+
+        // unrelated registers keep it
+        bq769x0.set_temperature_source(&mut i2c, TemperatureSource::ExternalThermistor).unwrap();
+        bq769x0.write_raw(&mut i2c, regs::SYS_CTRL2, &[0]).unwrap();
+        assert_eq!(bq769x0.known_temperature_source(), Some(TemperatureSource::ExternalThermistor));
+
+        bq769x0.wake(&mut i2c, &mut delay).unwrap();
+        assert_eq!(bq769x0.known_temperature_source(), None);
+
+        bq769x0.set_temperature_source(&mut i2c, TemperatureSource::ExternalThermistor).unwrap();
+        bq769x0.reset(&mut i2c).unwrap();
+        assert_eq!(bq769x0.known_temperature_source(), None);
+
+        bq769x0.set_temperature_source(&mut i2c, TemperatureSource::ExternalThermistor).unwrap();
+        i2c.regs[0x00] = SysStat::DEVICE_XREADY.bits();
+        bq769x0.recover_xready(&mut i2c, &mut delay).unwrap();
+        assert_eq!(bq769x0.known_temperature_source(), None);
+    }
+
+    #[test]
+    fn protection_manager_trip_and_recover() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        let mut manager = ProtectionManager::new(2);
+        assert!(matches!(manager.poll(&mut bq769x0, &mut i2c), Err(Error::Uninitialized)));
+        bq769x0.init_complete = true;
+        i2c.regs[0x05] = 0b11;
+        assert_eq!(manager.poll(&mut bq769x0, &mut i2c).unwrap(), ProtectionState::Normal);
+
+        i2c.regs[0x00] = (SysStat::SHORTCIRCUIT | SysStat::CC_READY).bits();
+        assert_eq!(manager.poll(&mut bq769x0, &mut i2c).unwrap(), ProtectionState::Tripped(SysStat::SHORTCIRCUIT));
+        assert_eq!(i2c.regs[0x05], 0);
+        // latched flags are kept during the cooldown
+        assert_eq!(manager.poll(&mut bq769x0, &mut i2c).unwrap(), ProtectionState::Tripped(SysStat::SHORTCIRCUIT));
+        assert_eq!(i2c.regs[0x00] & SysStat::SHORTCIRCUIT.bits(), SysStat::SHORTCIRCUIT.bits());
+        assert_eq!(manager.poll(&mut bq769x0, &mut i2c).unwrap(), ProtectionState::Recovering);
+        assert_eq!(i2c.regs[0x00], SysStat::CC_READY.bits());
+        assert_eq!(i2c.regs[0x05], 0);
+        assert_eq!(manager.poll(&mut bq769x0, &mut i2c).unwrap(), ProtectionState::Normal);
+        assert_eq!(i2c.regs[0x05], 0b11);
+
+        // fault comes back while recovering
+        i2c.regs[0x00] = SysStat::UNDERVOLTAGE.bits();
+        manager.poll(&mut bq769x0, &mut i2c).unwrap();
+        manager.poll(&mut bq769x0, &mut i2c).unwrap();
+        assert_eq!(manager.poll(&mut bq769x0, &mut i2c).unwrap(), ProtectionState::Recovering);
+        i2c.regs[0x00] = SysStat::UNDERVOLTAGE.bits();
+        assert_eq!(manager.poll(&mut bq769x0, &mut i2c).unwrap(), ProtectionState::Tripped(SysStat::UNDERVOLTAGE));
+        assert_eq!(manager.state(), ProtectionState::Tripped(SysStat::UNDERVOLTAGE));
+        assert_eq!(i2c.regs[0x05], 0);
+    }
+
+    #[test]
+    fn discharge_floor_blocks_dsg() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init_complete = true;
+        bq769x0.set_discharge_floor(Some(MilliVolts(2800)));
+        assert_eq!(bq769x0.discharge_floor(), Some(MilliVolts(2800)));
+        assert!(matches!(bq769x0.discharge(&mut i2c, true), Err(Error::Uninitialized)));
+
+        bq769x0.cells = [MilliVolts(3300), MilliVolts(3310), MilliVolts(2750), MilliVolts(3290), MilliVolts(3300)];
+        bq769x0.cells_read = true;
+        assert!(matches!(bq769x0.discharge(&mut i2c, true), Err(Error::CellBelowFloor(2, MilliVolts(2750)))));
+        assert!(matches!(bq769x0.enable_protection_outputs(&mut i2c), Err(Error::CellBelowFloor(2, _))));
+        assert_eq!(i2c.writes, 0);
+        // turning discharge off and charging are still allowed
+        assert!(bq769x0.discharge(&mut i2c, false).is_ok());
+        bq769x0.charge(&mut i2c, true).unwrap();
+        assert_eq!(i2c.regs[0x05], 0b01);
+
+        bq769x0.cells[2] = MilliVolts(2900);
+        bq769x0.discharge(&mut i2c, true).unwrap();
+        assert_eq!(i2c.regs[0x05], 0b11);
+        bq769x0.cells[2] = MilliVolts(2750);
+        bq769x0.set_discharge_floor(None);
+        assert!(bq769x0.enable_protection_outputs(&mut i2c).is_ok());
+    }
+
+    #[test]
+    fn protection_manager_respects_discharge_floor() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init_complete = true;
+        bq769x0.set_discharge_floor(Some(MilliVolts(2800)));
+        bq769x0.cells = [MilliVolts(3300), MilliVolts(3310), MilliVolts(2750), MilliVolts(3290), MilliVolts(3300)];
+        bq769x0.cells_read = true;
+        let mut manager = ProtectionManager::new(1);
+        i2c.regs[0x05] = 0b11;
+        i2c.regs[0x00] = SysStat::OVERCURRENT.bits();
+        assert_eq!(manager.poll(&mut bq769x0, &mut i2c).unwrap(), ProtectionState::Tripped(SysStat::OVERCURRENT));
+        assert_eq!(manager.poll(&mut bq769x0, &mut i2c).unwrap(), ProtectionState::Recovering);
+
+        // fault gone, but a cell is below the floor: FETs stay off
+        assert_eq!(manager.poll(&mut bq769x0, &mut i2c).unwrap(), ProtectionState::Recovering);
+        assert_eq!(manager.poll(&mut bq769x0, &mut i2c).unwrap(), ProtectionState::Recovering);
+        assert_eq!(i2c.regs[0x05], 0);
+
+        bq769x0.cells[2] = MilliVolts(2900);
+        assert_eq!(manager.poll(&mut bq769x0, &mut i2c).unwrap(), ProtectionState::Normal);
+        assert_eq!(i2c.regs[0x05], 0b11);
+    }
+
+    #[test]
+    fn delay_durations() {
+        use crate::*;
+
+        let scd = [(SCDDelay::_70uS, 70), (SCDDelay::_100uS, 100), (SCDDelay::_200uS, 200), (SCDDelay::_400uS, 400)];
+        for (delay, us) in scd.iter() {
+            assert_eq!(delay.micros(), *us);
+        }
+        let ocd = [
+            (OCDDelay::_8ms, 8), (OCDDelay::_20ms, 20), (OCDDelay::_40ms, 40), (OCDDelay::_80ms, 80),
+            (OCDDelay::_160ms, 160), (OCDDelay::_320ms, 320), (OCDDelay::_640ms, 640), (OCDDelay::_1280ms, 1280)
+        ];
+        for (delay, ms) in ocd.iter() {
+            assert_eq!(delay.millis(), *ms);
+            assert_eq!(delay.micros(), *ms * 1000);
+        }
+        let uv = [(UVDelay::_1s, 1000), (UVDelay::_4s, 4000), (UVDelay::_8s, 8000), (UVDelay::_16s, 16000)];
+        for (delay, ms) in uv.iter() {
+            assert_eq!(delay.millis(), *ms);
+            assert_eq!(delay.micros(), *ms * 1000);
+        }
+        let ov = [(OVDelay::_1s, 1000), (OVDelay::_4s, 4000), (OVDelay::_8s, 8000), (OVDelay::_16s, 16000)];
+        for (delay, ms) in ov.iter() {
+            assert_eq!(delay.millis(), *ms);
+            assert_eq!(delay.micros(), *ms * 1000);
+        }
+    }
+
+    #[test]
+    fn single_threshold_setters() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert!(matches!(bq769x0.set_ov_threshold(&mut i2c, MilliVolts(4200)), Err(Error::Uninitialized)));
+        assert!(matches!(bq769x0.set_scd(&mut i2c, Amperes(100), SCDDelay::_70uS), Err(Error::Uninitialized)));
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        let protect3 = i2c.regs[0x08];
+
+        let expected = bq769x0.compute_config(&Config { ov_threshold: MilliVolts(4200), uv_threshold: MilliVolts(2500), ..test_config() }).unwrap();
+        let (ov_bits, uv_bits) = (i2c.regs[0x09], i2c.regs[0x0a]);
+        assert_eq!(bq769x0.set_ov_threshold(&mut i2c, MilliVolts(4200)).unwrap(), expected.ov_threshold);
+        assert_ne!(i2c.regs[0x09], ov_bits);
+        assert_eq!(i2c.regs[0x0a], uv_bits);
+        assert_eq!(bq769x0.set_uv_threshold(&mut i2c, MilliVolts(2500)).unwrap(), expected.uv_threshold);
+        assert_ne!(i2c.regs[0x0a], uv_bits);
+        assert_eq!(i2c.regs[0x08], protect3);
+        assert!(matches!(bq769x0.set_uv_threshold(&mut i2c, MilliVolts(100)), Err(Error::UVThresholdUnobtainable(_, _))));
+        assert!(matches!(bq769x0.set_ov_threshold(&mut i2c, MilliVolts(9000)), Err(Error::OVThresholdUnobtainable(_, _))));
+
+        // 667uOhm shunt: SCD 200A = 133mV and OCD 100A = 67mV, both in the upper range
+        let protect1 = i2c.regs[0x06];
+        assert_eq!(protect1 & 0x87, 0x80 | 0x4);
+        assert_eq!(i2c.regs[0x07] & 0x0f, 0x9);
+        // 22mV exists in both ranges, RSNS stays
+        assert_eq!(bq769x0.set_ocd(&mut i2c, Amperes(30), OCDDelay::_20ms).unwrap(), Amperes(32));
+        assert_eq!((i2c.regs[0x06], i2c.regs[0x07]), (protect1, 0x11));
+        // 33mV needs the lower range, OCD is moved to its lower range bits
+        assert_eq!(bq769x0.set_scd(&mut i2c, Amperes(40), SCDDelay::_70uS).unwrap(), Amperes(49));
+        assert_eq!((i2c.regs[0x06], i2c.regs[0x07]), (0x01, 0x15));
+        assert_eq!(bq769x0.set_ocd(&mut i2c, Amperes(10), OCDDelay::_8ms).unwrap(), Amperes(11));
+        assert_eq!((i2c.regs[0x06], i2c.regs[0x07]), (0x01, 0x00));
+        // 178mV is upper range only, 8mV OCD is lower range only
+        let writes = i2c.writes;
+        assert!(matches!(bq769x0.set_scd(&mut i2c, Amperes(250), SCDDelay::_400uS), Err(Error::OCDSCDRangeMismatch(_))));
+        assert!(matches!(bq769x0.set_scd(&mut i2c, Amperes(1000), SCDDelay::_400uS), Err(Error::SCDThresholdUnobtainable(_))));
+        assert_eq!(i2c.writes, writes);
+        assert_eq!((i2c.regs[0x06], i2c.regs[0x07]), (0x01, 0x00));
+    }
+
+    #[test]
+    fn current_in_signed_amperes() {
+        use crate::*;
+
+        assert_eq!(MilliAmperes(1499).to_amperes(), SignedAmperes(1));
+        assert_eq!(MilliAmperes(1500).to_amperes(), SignedAmperes(2));
+        assert_eq!(MilliAmperes(-1500).to_amperes(), SignedAmperes(-2));
+        assert_eq!(MilliAmperes(-499).to_amperes(), SignedAmperes(0));
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        // 1000 * 8440nV / 667uOhm = 12.653A
+        i2c.regs[0x32..0x34].copy_from_slice(&1000i16.to_be_bytes());
+        assert_eq!(bq769x0.current_amperes(&mut i2c).unwrap(), SignedAmperes(13));
+        i2c.regs[0x32..0x34].copy_from_slice(&(-1000i16).to_be_bytes());
+        assert_eq!(bq769x0.current_amperes(&mut i2c).unwrap(), SignedAmperes(-13));
+        bq769x0.set_current_polarity(CurrentPolarity::DischargePositive);
+        assert_eq!(bq769x0.current_amperes(&mut i2c).unwrap(), SignedAmperes(13));
+    }
+
+    #[test]
+    fn die_temperature_restores_source() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut delay = DummyDelay { total_ms: 0 };
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert!(matches!(bq769x0.die_temperature(&mut i2c, &mut delay), Err(Error::Uninitialized)));
+        bq769x0.init_complete = true;
+        // 2921 * 382uV = 1.1158V, 84mV below V_25
+        i2c.regs[0x2c..0x2e].copy_from_slice(&2921u16.to_be_bytes());
+
+        i2c.regs[0x04] = (SysCtrl1::ADC_EN | SysCtrl1::TEMP_SEL).bits();
+        assert_eq!(bq769x0.die_temperature(&mut i2c, &mut delay).unwrap(), DegreesCentigrade(45));
+        assert_eq!(delay.total_ms, TEMP_SOURCE_SWITCH_MS as u32);
+        assert_eq!(i2c.regs[0x04], (SysCtrl1::ADC_EN | SysCtrl1::TEMP_SEL).bits());
+        assert_eq!(bq769x0.known_temperature_source(), Some(TemperatureSource::ExternalThermistor));
+
+        // already on the die sensor, no switching
+        i2c.regs[0x04] = SysCtrl1::ADC_EN.bits();
+        let writes = i2c.writes;
+        assert_eq!(bq769x0.die_temperature(&mut i2c, &mut delay).unwrap(), DegreesCentigrade(45));
+        assert_eq!(i2c.writes, writes);
+        assert_eq!(delay.total_ms, TEMP_SOURCE_SWITCH_MS as u32);
+    }
+
+    #[test]
+    fn coulomb_counter_mode_read_back() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        i2c.regs[0x05] = SysCtrl2::DSG_ON.bits();
+        for mode in [CoulombCounterMode::OneShot, CoulombCounterMode::Continuous, CoulombCounterMode::Disabled].iter() {
+            bq769x0.coulomb_counter_mode(&mut i2c, *mode).unwrap();
+            assert_eq!(bq769x0.read_coulomb_counter_mode(&mut i2c).unwrap(), *mode);
+        }
+        // CC_EN takes precedence over a stale CC_ONESHOT
+        i2c.regs[0x05] = (SysCtrl2::CC_EN | SysCtrl2::CC_ONESHOT).bits();
+        assert_eq!(bq769x0.read_coulomb_counter_mode(&mut i2c).unwrap(), CoulombCounterMode::Continuous);
+    }
+
+    #[test]
+    fn cell_offsets_follow_remapping() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        // 4 cells on a BQ76920: VC4 is shorted, the 4th cell is VC5
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 4, false).unwrap();
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        for (n, raw) in [6000u16, 6100, 6200, 0, 6400].iter().enumerate() {
+            i2c.regs[0x0c + n * 2..0x0e + n * 2].copy_from_slice(&raw.to_be_bytes());
+        }
+        let plain: std::vec::Vec<MilliVolts> = bq769x0.cell_voltages(&mut i2c).unwrap().to_vec();
+        assert_eq!(plain[3], bq769x0.convert_adc(6400));
+
+        assert!(matches!(bq769x0.set_cell_offsets(&[0; 6]), Err(Error::BufTooLarge)));
+        bq769x0.set_cell_offsets(&[5, -3, 0, 12]).unwrap();
+        assert_eq!(bq769x0.cell_offsets(), &[5, -3, 0, 12, 0]);
+        let cells = bq769x0.cell_voltages(&mut i2c).unwrap();
+        assert_eq!(cells, &[plain[0] + MilliVolts(5), plain[1] - MilliVolts(3), plain[2], plain[3] + MilliVolts(12)]);
+        // raw channel readings stay uncorrected
+        assert_eq!(bq769x0.cell_voltages_physical(&mut i2c).unwrap()[4], plain[3]);
+    }
+
+    fn check_crc_read_boundaries<const X: usize>(cell_count: u8) {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<X>::new(0x08, cell_count, true).unwrap();
+        let data: std::vec::Vec<u8> = (0..MAX_READ_LEN as u8 + 1).map(|b| b.wrapping_mul(7)).collect();
+
+        // exactly the cell block
+        i2c.scripted_reads.push_back((0x0c, crc_block(&[(0x08 << 1) | 1], &data[..X * 2])));
+        let raw = bq769x0.cell_voltages_raw(&mut i2c).unwrap();
+        assert_eq!(raw[0], u16::from_be_bytes([data[0], data[1]]));
+        assert_eq!(raw[X - 1], u16::from_be_bytes([data[X * 2 - 2], data[X * 2 - 1]]));
+
+        // one past the cell block and up to MAX_READ_LEN go through the same path
+        for len in [X * 2 - 1, X * 2, X * 2 + 1, MAX_READ_LEN].iter() {
+            let mut buf = [0u8; MAX_READ_LEN + 1];
+            i2c.scripted_reads.push_back((0x0c, crc_block(&[(0x08 << 1) | 1], &data[..*len])));
+            bq769x0.read_raw(&mut i2c, 0x0c, &mut buf[..*len]).unwrap();
+            assert_eq!(&buf[..*len], &data[..*len]);
+        }
+
+        let reads = i2c.reads;
+        let mut buf = [0u8; MAX_READ_LEN + 1];
+        assert!(matches!(bq769x0.read_raw(&mut i2c, 0x0c, &mut buf), Err(Error::BufTooLarge)));
+        assert_eq!(i2c.reads, reads);
+    }
+
+    #[test]
+    fn crc_read_boundaries_smallest_and_largest_chip() {
+        use crate::*;
+
+        check_crc_read_boundaries::<BQ76920>(5);
+        check_crc_read_boundaries::<BQ76940>(15);
+    }
+
+    #[test]
+    fn ship_enter_verified_sequence() {
+        use crate::*;
+        use crate::mock::{I2cMock, Transaction};
+
+        let sequence = || std::vec![
+            Transaction::write(0x08, regs::SYS_CTRL1, &[0b00]),
+            Transaction::write(0x08, regs::SYS_CTRL1, &[0b01]),
+            Transaction::write(0x08, regs::SYS_CTRL1, &[0b10]),
+        ];
+        let mut expected = sequence();
+        expected.push(Transaction::read(0x08, regs::SYS_CTRL1, &[0]).with_nack());
+        let mut i2c = I2cMock::new(&expected);
+        let mut delay = DummyDelay { total_ms: 0 };
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init_complete = true;
+        bq769x0.ship_enter_verified(&mut i2c, &mut delay).unwrap();
+        assert_eq!(delay.total_ms, (2 * SHIP_WRITE_DELAY_MS + SHIP_SHUTDOWN_MS) as u32);
+        assert!(!bq769x0.is_initialized());
+        i2c.done();
+
+        // still awake, e.g. the sequence was interrupted
+        let mut expected = sequence();
+        expected.push(Transaction::read(0x08, regs::SYS_CTRL1, &[0b10]));
+        let mut i2c = I2cMock::new(&expected);
+        bq769x0.init_complete = true;
+        assert!(matches!(bq769x0.ship_enter_verified(&mut i2c, &mut delay), Err(Error::ShipModeFailed)));
+        // ADC_EN was cleared all the same
+        assert!(!bq769x0.is_initialized());
+        i2c.done();
+
+        let mut i2c = I2cMock::new(&sequence());
+        bq769x0.init_complete = true;
+        bq769x0.ship_enter(&mut i2c).unwrap();
+        assert!(!bq769x0.is_initialized());
+        i2c.done();
+    }
+
+    #[test]
+    fn dyn_wrapper_per_variant() {
+        use crate::*;
+
+        for (variant, cells) in [(BQ76920, 5u8), (BQ76930, 10), (BQ76940, 15)].iter() {
+            let mut i2c = DummyI2C::new();
+            let mut bq769x0 = DynBq769x0::new(*variant, 0x08, *cells, false).unwrap();
+            assert_eq!(bq769x0.variant(), *variant);
+            assert_eq!((bq769x0.cell_count(), bq769x0.i2c_address()), (*cells, 0x08));
+            assert!(matches!(bq769x0.cell_voltages(&mut i2c), Err(Error::Uninitialized)));
+            bq769x0.init(&mut i2c, &test_config()).unwrap();
+            assert!(bq769x0.is_initialized());
+            assert_eq!(bq769x0.cell_voltages(&mut i2c).unwrap().len(), *cells as usize);
+            assert_eq!(bq769x0.temperature_all(&mut i2c).unwrap().len(), *variant / 5);
+            bq769x0.discharge(&mut i2c, true).unwrap();
+            assert_eq!(i2c.regs[0x05] & 0b10, 0b10);
+        }
+        assert!(matches!(DynBq769x0::new(7, 0x08, 5, false), Err(Error::InvalidVariant(7))));
+        assert!(matches!(DynBq769x0::new(BQ76930, 0x08, 5, false), Err(Error::InvalidCellCount(5))));
+    }
+
+    #[test]
+    fn protection_manager_clears_only_defined_flags() {
+        use crate::*;
+
+        // reserved bit 6 is never written back
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        bq769x0.init_complete = true;
+        let mut manager = ProtectionManager::new(1);
+        i2c.regs[0x00] = 0b0100_0000 | SysStat::OVERCURRENT.bits();
+        manager.poll(&mut bq769x0, &mut i2c).unwrap();
+        assert_eq!(manager.poll(&mut bq769x0, &mut i2c).unwrap(), ProtectionState::Recovering);
+        assert_eq!(i2c.regs[0x00], 0b0100_0000);
+    }
+
+    #[test]
+    fn sys_stat_serde_round_trip() {
+        use crate::*;
+
+        let stat = SysStat::CC_READY | SysStat::DEVICE_XREADY | SysStat::UNDERVOLTAGE | SysStat::SHORTCIRCUIT;
+        let mut buf = [0u8; 8];
+        let bytes = postcard::to_slice(&stat, &mut buf).unwrap();
+        assert_eq!(bytes, &[0b1010_1010]);
+        assert_eq!(postcard::from_bytes::<SysStat>(bytes).unwrap(), stat);
+        assert_eq!(postcard::from_bytes::<SysStat>(&[0xff]).unwrap(), SysStat::ALL);
+        assert!(postcard::from_bytes::<SysStat>(&[]).is_err());
+    }
+
+    #[test]
+    fn balance_targets_from_last_reading() {
+        use crate::*;
+
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 4, false).unwrap();
+        assert!(matches!(bq769x0.balance_targets(MilliVolts(3300)), Err(Error::Uninitialized)));
+        bq769x0.cells = [MilliVolts(3350), MilliVolts(3290), MilliVolts(3300), MilliVolts(3412), MilliVolts(3500)];
+        bq769x0.cells_read = true;
+        assert_eq!(bq769x0.balance_targets(MilliVolts(3300)).unwrap(),
+                   [MilliVolts(50), MilliVolts(0), MilliVolts(0), MilliVolts(112), MilliVolts(0)]);
+    }
+
+    #[test]
+    fn read_by_value() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        i2c.regs[0x2a..0x2c].copy_from_slice(&[0x12, 0x34]);
+        assert_eq!(bq769x0.read_u8(&mut i2c, 0x2a).unwrap(), 0x12);
+        assert_eq!(bq769x0.read_u16_be(&mut i2c, 0x2a).unwrap(), 0x1234);
+        i2c.regs[0x32..0x34].copy_from_slice(&(-2i16).to_be_bytes());
+        assert_eq!(bq769x0.coulomb_counter_raw(&mut i2c).unwrap(), -2);
+
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, true).unwrap();
+        i2c.scripted_reads.push_back((0x00, crc_block(&[(0x08 << 1) | 1], &[0b1000_0001])));
+        assert_eq!(bq769x0.sys_stat(&mut i2c).unwrap(), SysStat::CC_READY | SysStat::OVERCURRENT);
+        i2c.scripted_reads.push_back((0x2a, crc_block(&[(0x08 << 1) | 1], &[0xab, 0xcd])));
+        assert_eq!(bq769x0.read_u16_be(&mut i2c, 0x2a).unwrap(), 0xabcd);
+        let mut corrupted = crc_block(&[(0x08 << 1) | 1], &[0xab, 0xcd]);
+        corrupted[3] ^= 1;
+        i2c.scripted_reads.push_back((0x2a, corrupted));
+        assert!(matches!(bq769x0.read_u16_be(&mut i2c, 0x2a), Err(Error::CRCMismatch)));
+        i2c.nack = true;
+        assert!(matches!(bq769x0.read_u8(&mut i2c, 0x2a), Err(Error::I2CError)));
+    }
+
+    #[test]
+    fn read_cells_into_caller_buffer() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76930>::new(0x08, 8, false).unwrap();
+        let mut out = [MilliVolts(0); BQ76940];
+        assert!(matches!(bq769x0.read_cells_into(&mut i2c, &mut out), Err(Error::Uninitialized)));
+        bq769x0.init(&mut i2c, &test_config()).unwrap();
+        for n in 0..10u16 {
+            let raw = 6000 + n * 10;
+            i2c.regs[0x0c + n as usize * 2..0x0e + n as usize * 2].copy_from_slice(&raw.to_be_bytes());
+        }
+        assert_eq!(bq769x0.read_cells_into(&mut i2c, &mut out).unwrap(), 8);
+        // the buffer stays usable while the driver is used again
+        bq769x0.discharge(&mut i2c, false).unwrap();
+        assert_eq!(&out[..8], bq769x0.cell_voltages(&mut i2c).unwrap());
+        assert_eq!(out[8], MilliVolts(0));
+        assert!(matches!(bq769x0.read_cells_into(&mut i2c, &mut out[..7]), Err(Error::BufTooSmall(8))));
+
+        let mut dyn_bq = DynBq769x0::new(BQ76920, 0x08, 5, false).unwrap();
+        dyn_bq.init(&mut i2c, &test_config()).unwrap();
+        assert_eq!(dyn_bq.read_cells_into(&mut i2c, &mut out).unwrap(), 5);
+    }
+
+    #[test]
+    fn legal_balancing_mask_drops_adjacent_cells() {
+        use crate::*;
+
+        assert_eq!(BQ769x0::<BQ76920>::legal_balancing_mask(0b1_0101), 0b1_0101);
+        assert_eq!(BQ769x0::<BQ76920>::legal_balancing_mask(0b0_0111), 0b0_0101);
+        assert_eq!(BQ769x0::<BQ76920>::legal_balancing_mask(0b1_1111), 0b1_0101);
+        // cells 4 and 5 are in different groups and may balance together
+        assert_eq!(BQ769x0::<BQ76930>::legal_balancing_mask(0b11_0000), 0b11_0000);
+        assert_eq!(BQ769x0::<BQ76930>::legal_balancing_mask(0b1110_0000), 0b1010_0000);
+        // cells beyond the chip are ignored
+        assert_eq!(BQ769x0::<BQ76920>::legal_balancing_mask(0b10_0000), 0);
+    }
+
+    #[test]
+    fn balance_cells_writes_legal_subset() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76930>::new(0x08, 10, false).unwrap();
+        let enabled = bq769x0.balance_cells(&mut i2c, &[0, 1, 2, 6, 7]).unwrap();
+        assert_eq!(enabled, 0b00_0100_0101);
+        assert_eq!(i2c.regs[0x01], 0b0_0101);
+        assert_eq!(i2c.regs[0x02], 0b0_0010);
+
+        let writes = i2c.writes;
+        assert!(matches!(bq769x0.balance_cells(&mut i2c, &[0, 12]), Err(Error::InvalidCell(12))));
+        assert!(matches!(bq769x0.balance_cells(&mut i2c, &[10]), Err(Error::InvalidCell(10))));
+        assert_eq!(i2c.writes, writes);
+    }
+
+    #[test]
+    fn enable_balancing_rejects_adjacent_cells() {
+        use crate::*;
+
+        let mut i2c = DummyI2C::new();
+        let mut bq769x0 = BQ769x0::<BQ76920>::new(0x08, 5, false).unwrap();
+        assert!(bq769x0.enable_balancing(&mut i2c, 0b1_0101).is_ok());
+        assert_eq!(i2c.regs[0x01], 0b1_0101);
+        assert!(matches!(bq769x0.enable_balancing(&mut i2c, 0b0_1100), Err(Error::AdjacentBalancing)));
+        assert_eq!(i2c.regs[0x01], 0b1_0101);
+
+        assert!(!BQ769x0::<BQ76930>::has_adjacent_cells(0b11_0000));
+        assert!(BQ769x0::<BQ76930>::has_adjacent_cells(0b1100_0000));
+        assert!(!BQ769x0::<BQ76920>::has_adjacent_cells(0));
+    }
 }